@@ -21,6 +21,16 @@ pub struct Blas<T> {
 
 impl<T: AsStd140> Blas<T> {
     pub fn build(&self, cache: &mut HashPool, rgraph: &mut RenderGraph) {
+        self.record(cache, rgraph, false);
+    }
+    ///
+    /// Refits the BLAS in place to the current vertex positions, the topology has to be
+    /// unchanged. Requires a build with `ALLOW_UPDATE`.
+    ///
+    pub fn update(&self, cache: &mut HashPool, rgraph: &mut RenderGraph) {
+        self.record(cache, rgraph, true);
+    }
+    fn record(&self, cache: &mut HashPool, rgraph: &mut RenderGraph, update: bool) {
         //let geometry = scene.geometries.get(self.geometry).unwrap();
         let indices = self.indices.clone();
         let positions = self.positions.clone();
@@ -32,7 +42,11 @@ impl<T: AsStd140> Blas<T> {
             cache
                 .lease(
                     BufferInfo::new(
-                        self.size.build_size,
+                        if update {
+                            self.size.update_size
+                        } else {
+                            self.size.build_size
+                        },
                         vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
                             | vk::BufferUsageFlags::STORAGE_BUFFER,
                     )
@@ -54,26 +68,36 @@ impl<T: AsStd140> Blas<T> {
         let primitive_count = self.primitive_count;
 
         rgraph
-            .begin_pass("Build BLAS")
+            .begin_pass(if update { "Update BLAS" } else { "Build BLAS" })
             .read_node(index_node)
             .read_node(vertex_node)
             .write_node(accel_node)
             .write_node(scratch_buf)
             .record_acceleration(move |accel, _| {
-                accel.build_structure(
-                    accel_node,
-                    scratch_buf,
-                    &geometry_info,
-                    &[vk::AccelerationStructureBuildRangeInfoKHR {
-                        first_vertex: 0,
-                        primitive_count: primitive_count as u32,
-                        primitive_offset: 0,
-                        transform_offset: 0,
-                    }],
-                )
+                let ranges = [vk::AccelerationStructureBuildRangeInfoKHR {
+                    first_vertex: 0,
+                    primitive_count: primitive_count as u32,
+                    primitive_offset: 0,
+                    transform_offset: 0,
+                }];
+                if update {
+                    accel.update_structure(
+                        accel_node,
+                        accel_node,
+                        scratch_buf,
+                        &geometry_info,
+                        &ranges,
+                    )
+                } else {
+                    accel.build_structure(accel_node, scratch_buf, &geometry_info, &ranges)
+                }
             });
         //AnyAccelerationStructureNode::AccelerationStructure(accel_node)
     }
+    ///
+    /// Creates the BLAS for `primitive_count` triangles, `flags` select the build options such as
+    /// `PREFER_FAST_TRACE` or `ALLOW_COMPACTION`.
+    ///
     pub fn create(
         device: &Arc<Device>,
         indices: &Array<u32>,
@@ -81,6 +105,7 @@ impl<T: AsStd140> Blas<T> {
         primitive_count: usize,
         vertices: &Array<T>,
         vertices_offset: usize,
+        flags: vk::BuildAccelerationStructureFlagsKHR,
     ) -> Self {
         //let triangle_count = geometry.indices.count() / 3;
         let vertex_count = vertices.count() as u64;
@@ -89,7 +114,7 @@ impl<T: AsStd140> Blas<T> {
 
         let geometry_info = AccelerationStructureGeometryInfo {
             ty: vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
-            flags: vk::BuildAccelerationStructureFlagsKHR::empty(),
+            flags,
             geometries: vec![AccelerationStructureGeometry {
                 max_primitive_count: primitive_count as _,
                 flags: vk::GeometryFlagsKHR::OPAQUE,
@@ -130,6 +155,141 @@ impl<T: AsStd140> Blas<T> {
     }
 }
 
+impl<T> Blas<T> {
+    ///
+    /// Size of the acceleration structure as built.
+    ///
+    pub fn size(&self) -> u64 {
+        self.size.create_size
+    }
+    ///
+    /// Copies the built BLAS into a new structure of `compacted_size` bytes and drops the
+    /// original once the copy has executed. Requires a build with `ALLOW_COMPACTION`.
+    ///
+    pub fn compact(&mut self, rgraph: &mut RenderGraph, compacted_size: u64) {
+        let compacted = Arc::new(
+            AccelerationStructure::create(
+                &self.device,
+                AccelerationStructureInfo::blas(compacted_size),
+            )
+            .unwrap(),
+        );
+        let src_node = rgraph.bind_node(&self.accel);
+        let dst_node = rgraph.bind_node(&compacted);
+
+        rgraph
+            .begin_pass("Compact BLAS")
+            .access_node(src_node, AccessType::AccelerationStructureBuildRead)
+            .access_node(dst_node, AccessType::AccelerationStructureBuildWrite)
+            .record_cmd_buf(move |device, cmd_buf, bindings| unsafe {
+                device
+                    .accel_struct_ext
+                    .as_ref()
+                    .unwrap()
+                    .cmd_copy_acceleration_structure(
+                        cmd_buf,
+                        &vk::CopyAccelerationStructureInfoKHR::builder()
+                            .src(**bindings[src_node])
+                            .dst(**bindings[dst_node])
+                            .mode(vk::CopyAccelerationStructureModeKHR::COMPACT),
+                    );
+            });
+
+        self.accel = compacted;
+        self.size.create_size = compacted_size;
+    }
+}
+
+///
+/// Query pool for the compacted sizes of BLASes built with `ALLOW_COMPACTION`.
+///
+pub struct CompactionQuery {
+    device: Arc<Device>,
+    pool: vk::QueryPool,
+    count: usize,
+}
+
+impl CompactionQuery {
+    ///
+    /// Records the size query for all `blases` after their builds in `rgraph`, `blases` must not
+    /// be empty and all of them have to be built with `ALLOW_COMPACTION`.
+    ///
+    pub fn new<T>(device: &Arc<Device>, rgraph: &mut RenderGraph, blases: &[&Blas<T>]) -> Self {
+        let count = blases.len();
+        let pool = unsafe {
+            device.create_query_pool(
+                &vk::QueryPoolCreateInfo::builder()
+                    .query_type(vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR)
+                    .query_count(count as u32),
+                None,
+            )
+        }
+        .unwrap();
+
+        let accel_nodes = blases
+            .iter()
+            .map(|blas| rgraph.bind_node(&blas.accel))
+            .collect::<Vec<_>>();
+        let mut pass = rgraph.begin_pass("Query compacted BLAS sizes");
+        for accel_node in accel_nodes.iter() {
+            pass = pass.access_node(*accel_node, AccessType::AccelerationStructureBuildRead);
+        }
+        pass.record_cmd_buf(move |device, cmd_buf, bindings| unsafe {
+            let accels = accel_nodes
+                .iter()
+                .map(|node| **bindings[*node])
+                .collect::<Vec<_>>();
+            device.cmd_reset_query_pool(cmd_buf, pool, 0, count as u32);
+            device
+                .accel_struct_ext
+                .as_ref()
+                .unwrap()
+                .cmd_write_acceleration_structures_properties(
+                    cmd_buf,
+                    &accels,
+                    vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+                    pool,
+                    0,
+                );
+        });
+
+        Self {
+            device: device.clone(),
+            pool,
+            count,
+        }
+    }
+    ///
+    /// Compacted size of every BLAS, `None` while the query has not executed yet. The graph
+    /// recording the query has to be submitted before, the results are never available otherwise.
+    ///
+    pub fn sizes(&self) -> Option<Vec<u64>> {
+        let mut sizes = vec![0u64; self.count];
+        let result = unsafe {
+            self.device.get_query_pool_results(
+                self.pool,
+                0,
+                self.count as u32,
+                &mut sizes,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        match result {
+            Err(vk::Result::NOT_READY) => None,
+            result => {
+                result.unwrap();
+                Some(sizes)
+            }
+        }
+    }
+}
+
+impl Drop for CompactionQuery {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_query_pool(self.pool, None) };
+    }
+}
+
 pub struct Tlas {
     device: Arc<Device>,
     instance_buf: Arc<Array<u8>>,
@@ -147,25 +307,7 @@ impl Tlas {
         rgraph: &mut RenderGraph,
         blas_nodes: &[AnyAccelerationStructureNode],
     ) {
-        let scratch_buf = rgraph.bind_node(
-            cache
-                .lease(
-                    BufferInfo::new(
-                        self.size.build_size,
-                        vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                            | vk::BufferUsageFlags::STORAGE_BUFFER,
-                    )
-                    .alignment(
-                        self.device
-                            .physical_device
-                            .accel_struct_properties
-                            .as_ref()
-                            .unwrap()
-                            .min_accel_struct_scratch_offset_alignment as _,
-                    ),
-                )
-                .unwrap(),
-        );
+        let scratch_buf = self.scratch_buf(cache, rgraph, self.size.build_size);
         let accel_node = rgraph.bind_node(&self.accel);
         let instance_node = rgraph.bind_node(&self.instance_buf.buf);
         let tlas_node = rgraph.bind_node(&self.accel);
@@ -197,23 +339,107 @@ impl Tlas {
             });
         //println!("pass: {:#?}", rgraph);
     }
-    pub fn create(
-        device: &Arc<Device>,
+    ///
+    /// Refits the TLAS in place to new transforms of the same number of instances, the BLASes
+    /// are left untouched. Requires a build with `ALLOW_UPDATE`.
+    ///
+    pub fn update(
+        &mut self,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
         instances: &[vk::AccelerationStructureInstanceKHR],
-    ) -> Option<Self> {
-        if (instances.len() == 0) {
-            return None;
+        blas_nodes: &[AnyAccelerationStructureNode],
+    ) {
+        assert_eq!(instances.len(), self.instance_count);
+        self.instance_buf = Self::instance_buf(&self.device, instances);
+        self.geometry_info.geometries[0].geometry = AccelerationStructureGeometryData::Instances {
+            array_of_pointers: false,
+            data: DeviceOrHostAddress::DeviceAddress(Buffer::device_address(
+                &self.instance_buf.buf,
+            )),
+        };
+
+        let scratch_buf = self.scratch_buf(cache, rgraph, self.size.update_size);
+        let accel_node = rgraph.bind_node(&self.accel);
+        let instance_node = rgraph.bind_node(&self.instance_buf.buf);
+        let geometry_info = self.geometry_info.clone();
+        let primitive_count = self.instance_count;
+
+        let mut pass = rgraph.begin_pass("Update TLAS");
+        for blas_node in blas_nodes {
+            pass = pass.access_node(*blas_node, AccessType::AccelerationStructureBuildRead);
         }
+        pass.read_node(instance_node)
+            .write_node(scratch_buf)
+            .write_node(accel_node)
+            .record_acceleration(move |accel, _| {
+                accel.update_structure(
+                    accel_node,
+                    accel_node,
+                    scratch_buf,
+                    &geometry_info,
+                    &[vk::AccelerationStructureBuildRangeInfoKHR {
+                        primitive_count: primitive_count as _,
+                        primitive_offset: 0,
+                        first_vertex: 0,
+                        transform_offset: 0,
+                    }],
+                );
+            });
+    }
+    pub fn instance_count(&self) -> usize {
+        self.instance_count
+    }
+    fn instance_buf(
+        device: &Arc<Device>,
+        instances: &[vk::AccelerationStructureInstanceKHR],
+    ) -> Arc<Array<u8>> {
         // gl_CustomIndexEXT should index into attributes.
-        let instance_buf = Arc::new(Array::from_slice_u8(
+        // An empty TLAS is built from zero primitives of a dummy instance.
+        let dummy = [0u8; size_of::<vk::AccelerationStructureInstanceKHR>()];
+        let data = AccelerationStructure::instance_slice(instances);
+        Arc::new(Array::from_slice_u8(
             device,
             vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
                 | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-            AccelerationStructure::instance_slice(instances),
-        ));
+            if data.is_empty() { &dummy } else { data },
+        ))
+    }
+    fn scratch_buf(
+        &self,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
+        size: u64,
+    ) -> BufferLeaseNode {
+        rgraph.bind_node(
+            cache
+                .lease(
+                    BufferInfo::new(
+                        size,
+                        vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                            | vk::BufferUsageFlags::STORAGE_BUFFER,
+                    )
+                    .alignment(
+                        self.device
+                            .physical_device
+                            .accel_struct_properties
+                            .as_ref()
+                            .unwrap()
+                            .min_accel_struct_scratch_offset_alignment as _,
+                    ),
+                )
+                .unwrap(),
+        )
+    }
+    pub fn create(
+        device: &Arc<Device>,
+        instances: &[vk::AccelerationStructureInstanceKHR],
+        flags: vk::BuildAccelerationStructureFlagsKHR,
+    ) -> Self {
+        let instance_buf = Self::instance_buf(device, instances);
         let geometry_info = AccelerationStructureGeometryInfo {
             ty: vk::AccelerationStructureTypeKHR::TOP_LEVEL,
-            flags: vk::BuildAccelerationStructureFlagsKHR::empty(),
+            flags,
             geometries: vec![AccelerationStructureGeometry {
                 max_primitive_count: instances.len() as _,
                 flags: vk::GeometryFlagsKHR::OPAQUE,
@@ -232,13 +458,13 @@ impl Tlas {
 
         let accel = Arc::new(AccelerationStructure::create(device, info).unwrap());
 
-        Some(Self {
+        Self {
             instance_buf,
             instance_count: instances.len(),
             size,
             geometry_info,
             accel,
             device: device.clone(),
-        })
+        }
     }
 }
@@ -0,0 +1,455 @@
+//! Keyframe animation of node transforms, following the glTF animation model.
+//! Animations are evaluated on the CPU and applied to the instances, cameras and deformed meshes
+//! of the scene, see `Scene::animate`. Skinning and morph targets are applied on the GPU by
+//! `DeformPass`.
+use crate::scene::Scene;
+use glam::*;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    /// Hermite spline, every keyframe stores an in-tangent, the value and an out-tangent.
+    CubicSpline,
+}
+
+///
+/// Node property targeted by a channel.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Path {
+    Translation,
+    /// Quaternion in xyzw order.
+    Rotation,
+    Scale,
+    /// Morph target weights of the mesh of the node.
+    Weights,
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    /// Index into `Animation::nodes`.
+    pub node: usize,
+    pub path: Path,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, ascending.
+    pub times: Vec<f32>,
+    /// `components` floats per value, one value per keyframe and three for `CubicSpline`.
+    pub values: Vec<f32>,
+    pub components: usize,
+}
+
+impl Channel {
+    ///
+    /// The `i`th value, which is the `i`th keyframe unless interpolation is `CubicSpline`.
+    ///
+    fn value(&self, i: usize) -> &[f32] {
+        &self.values[i * self.components..(i + 1) * self.components]
+    }
+    fn keyframe(&self, key: usize) -> &[f32] {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.value(3 * key + 1),
+            _ => self.value(key),
+        }
+    }
+    ///
+    /// Evaluates the channel at `time`, clamped to the first and last keyframe.
+    ///
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let n = self.times.len();
+        if time <= self.times[0] {
+            return self.keyframe(0).to_vec();
+        }
+        if time >= self.times[n - 1] {
+            return self.keyframe(n - 1).to_vec();
+        }
+        let k = self.times.partition_point(|&t| t <= time) - 1;
+        let dt = self.times[k + 1] - self.times[k];
+        let s = (time - self.times[k]) / dt;
+
+        match self.interpolation {
+            Interpolation::Step => self.keyframe(k).to_vec(),
+            Interpolation::Linear if self.path == Path::Rotation => {
+                let q0 = Quat::from_slice(self.keyframe(k));
+                let q1 = Quat::from_slice(self.keyframe(k + 1));
+                q0.slerp(q1, s).to_array().to_vec()
+            }
+            Interpolation::Linear => self
+                .keyframe(k)
+                .iter()
+                .zip(self.keyframe(k + 1))
+                .map(|(v0, v1)| v0 + (v1 - v0) * s)
+                .collect(),
+            Interpolation::CubicSpline => {
+                let v0 = self.value(3 * k + 1);
+                let b0 = self.value(3 * k + 2);
+                let a1 = self.value(3 * (k + 1));
+                let v1 = self.value(3 * (k + 1) + 1);
+                let (s2, s3) = (s * s, s * s * s);
+                let p = (0..self.components)
+                    .map(|i| {
+                        v0[i] * (2. * s3 - 3. * s2 + 1.)
+                            + b0[i] * dt * (s3 - 2. * s2 + s)
+                            + v1[i] * (-2. * s3 + 3. * s2)
+                            + a1[i] * dt * (s3 - s2)
+                    })
+                    .collect::<Vec<_>>();
+                if self.path == Path::Rotation {
+                    Quat::from_slice(&p).normalize().to_array().to_vec()
+                } else {
+                    p
+                }
+            }
+        }
+    }
+}
+
+///
+/// Joints of a skin, the joint matrices move the vertices of skinned meshes.
+///
+#[derive(Debug, Clone)]
+pub struct Skin {
+    /// Indices into `Animation::nodes`.
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+///
+/// Node of the animated hierarchy with its rest transform.
+///
+#[derive(Debug, Clone)]
+pub struct AnimationNode {
+    /// Parents precede their children in `Animation::nodes`.
+    pub parent: Option<usize>,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+    /// Scene instances placed by this node.
+    pub instances: Range<usize>,
+    /// Scene camera placed by this node.
+    pub camera: Option<usize>,
+    /// Scene deformers of the skinned or morphed primitives of the mesh of this node.
+    pub deformers: Vec<usize>,
+    /// Index into `Animation::skins`.
+    pub skin: Option<usize>,
+    /// Rest morph target weights.
+    pub weights: Vec<f32>,
+}
+
+///
+/// Channels of one glTF animation, only one clip of a file is played at a time.
+///
+#[derive(Debug, Clone)]
+pub struct Clip {
+    pub name: Option<String>,
+    pub channels: Vec<Channel>,
+    /// Time of the last keyframe, the clip loops after it.
+    pub duration: f32,
+}
+
+impl Clip {
+    pub fn new(name: Option<String>, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0., f32::max);
+        Self {
+            name,
+            channels,
+            duration,
+        }
+    }
+}
+
+///
+/// Node hierarchy of a scene file with its animation clips.
+///
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub nodes: Vec<AnimationNode>,
+    /// Clips in file order.
+    pub clips: Vec<Clip>,
+    pub skins: Vec<Skin>,
+    /// Whether the transform of a node or one of its ancestors is animated by any clip, the
+    /// other nodes keep the rest transform their instances and cameras were loaded with.
+    pub animated: Vec<bool>,
+}
+
+impl Animation {
+    pub fn new(nodes: Vec<AnimationNode>, clips: Vec<Clip>, skins: Vec<Skin>) -> Self {
+        let mut animated = vec![false; nodes.len()];
+        for channel in clips.iter().flat_map(|clip| clip.channels.iter()) {
+            if channel.path != Path::Weights {
+                animated[channel.node] = true;
+            }
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            if matches!(node.parent, Some(parent) if animated[parent]) {
+                animated[i] = true;
+            }
+        }
+        Self {
+            nodes,
+            clips,
+            skins,
+            animated,
+        }
+    }
+    ///
+    /// World space transform and morph target weights of every node at `time` of `clip`, the
+    /// rest pose if the file has no such clip.
+    ///
+    pub fn evaluate(&self, clip: usize, time: f32) -> (Vec<Mat4>, Vec<Vec<f32>>) {
+        let (channels, duration) = match self.clips.get(clip) {
+            Some(clip) => (clip.channels.as_slice(), clip.duration),
+            None => (&[][..], 0.),
+        };
+        let time = if duration > 0. {
+            time.rem_euclid(duration)
+        } else {
+            0.
+        };
+
+        let mut locals = self
+            .nodes
+            .iter()
+            .map(|node| (node.translation, node.rotation, node.scale))
+            .collect::<Vec<_>>();
+        let mut weights = self
+            .nodes
+            .iter()
+            .map(|node| node.weights.clone())
+            .collect::<Vec<_>>();
+        for channel in channels.iter() {
+            let value = channel.sample(time);
+            let local = &mut locals[channel.node];
+            match channel.path {
+                Path::Translation => local.0 = Vec3::from_slice(&value),
+                Path::Rotation => local.1 = Quat::from_slice(&value).normalize(),
+                Path::Scale => local.2 = Vec3::from_slice(&value),
+                Path::Weights => weights[channel.node] = value,
+            }
+        }
+
+        let mut to_world: Vec<Mat4> = Vec::with_capacity(self.nodes.len());
+        for (node, (translation, rotation, scale)) in self.nodes.iter().zip(locals) {
+            let local = Mat4::from_scale_rotation_translation(scale, rotation, translation);
+            to_world.push(match node.parent {
+                Some(parent) => to_world[parent] * local,
+                None => local,
+            });
+        }
+        (to_world, weights)
+    }
+    ///
+    /// Moves the instances and cameras of the animated nodes to their transforms at `time` of
+    /// `clip` and poses their skinned and morphed meshes.
+    ///
+    pub fn apply(&self, clip: usize, time: f32, scene: &mut Scene) {
+        let (to_world, weights) = self.evaluate(clip, time);
+        for (i, node) in self.nodes.iter().enumerate() {
+            // Moving static instances would upload all instances and refit the tlas every frame.
+            if self.animated[i] {
+                for instance in node.instances.clone() {
+                    scene.set_transform(instance, to_world[i]);
+                }
+                if let Some(camera) = node.camera {
+                    scene.cameras[camera].to_world = to_world[i];
+                }
+            }
+
+            if node.deformers.is_empty() {
+                continue;
+            }
+            // Joint matrices are relative to the node, which places the skinned mesh.
+            let joints = match node.skin {
+                Some(skin) => {
+                    let skin = &self.skins[skin];
+                    let to_node = to_world[i].inverse();
+                    skin.joints
+                        .iter()
+                        .zip(skin.inverse_bind_matrices.iter())
+                        .map(|(joint, inverse_bind)| to_node * to_world[*joint] * *inverse_bind)
+                        .collect()
+                }
+                None => vec![],
+            };
+            for deformer in node.deformers.iter() {
+                scene.set_deformation(*deformer, &joints, &weights[i]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(path: Path, interpolation: Interpolation, times: &[f32], values: &[f32]) -> Channel {
+        let count = match interpolation {
+            Interpolation::CubicSpline => 3 * times.len(),
+            _ => times.len(),
+        };
+        Channel {
+            node: 0,
+            path,
+            interpolation,
+            times: times.to_vec(),
+            values: values.to_vec(),
+            components: values.len() / count,
+        }
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-5, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn step() {
+        let channel = channel(
+            Path::Translation,
+            Interpolation::Step,
+            &[0., 1., 2.],
+            &[0., 10., 20.],
+        );
+        assert_close(&channel.sample(0.5), &[0.]);
+        assert_close(&channel.sample(1.), &[10.]);
+        assert_close(&channel.sample(1.99), &[10.]);
+    }
+
+    #[test]
+    fn linear() {
+        let channel = channel(
+            Path::Translation,
+            Interpolation::Linear,
+            &[1., 2., 4.],
+            &[0., 0., 0., 1., 2., 3., 3., 2., 1.],
+        );
+        assert_close(&channel.sample(1.5), &[0.5, 1., 1.5]);
+        assert_close(&channel.sample(2.), &[1., 2., 3.]);
+        assert_close(&channel.sample(3.), &[2., 2., 2.]);
+    }
+
+    #[test]
+    fn cubic_spline() {
+        // In-tangent, value and out-tangent per keyframe, tangents of a straight line.
+        let line = channel(
+            Path::Translation,
+            Interpolation::CubicSpline,
+            &[0., 2.],
+            &[0., 0., 1., 1., 2., 0.],
+        );
+        assert_close(&line.sample(0.5), &[0.5]);
+        assert_close(&line.sample(1.), &[1.]);
+
+        // Flat tangents give a smoothstep.
+        let smooth = channel(
+            Path::Translation,
+            Interpolation::CubicSpline,
+            &[0., 1.],
+            &[0., 0., 0., 0., 1., 0.],
+        );
+        assert_close(&smooth.sample(0.25), &[0.15625]);
+        assert_close(&smooth.sample(0.5), &[0.5]);
+    }
+
+    #[test]
+    fn clamped_to_first_and_last_keyframe() {
+        let linear = channel(
+            Path::Scale,
+            Interpolation::Linear,
+            &[1., 2.],
+            &[1., 1., 1., 2., 2., 2.],
+        );
+        assert_close(&linear.sample(0.), &[1., 1., 1.]);
+        assert_close(&linear.sample(3.), &[2., 2., 2.]);
+
+        // The keyframe values, not their tangents.
+        let cubic = channel(
+            Path::Translation,
+            Interpolation::CubicSpline,
+            &[0., 1.],
+            &[5., 0., 6., 7., 1., 8.],
+        );
+        assert_close(&cubic.sample(-1.), &[0.]);
+        assert_close(&cubic.sample(2.), &[1.]);
+    }
+
+    #[test]
+    fn rotation_is_slerped() {
+        let q0 = Quat::IDENTITY;
+        let q1 = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let values = [q0.to_array(), q1.to_array()].concat();
+        let channel = channel(Path::Rotation, Interpolation::Linear, &[0., 1.], &values);
+        let q = Quat::from_slice(&channel.sample(0.5));
+        assert!(q.is_normalized());
+        assert!(q.angle_between(Quat::from_rotation_y(std::f32::consts::FRAC_PI_4)) < 1e-4);
+    }
+
+    #[test]
+    fn cubic_rotation_is_normalized() {
+        let q0 = Quat::IDENTITY;
+        let q1 = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let zero = [0.; 4];
+        let values = [zero, q0.to_array(), zero, zero, q1.to_array(), zero].concat();
+        let channel = channel(
+            Path::Rotation,
+            Interpolation::CubicSpline,
+            &[0., 1.],
+            &values,
+        );
+        let q = Quat::from_slice(&channel.sample(0.5));
+        assert!(q.is_normalized());
+        assert!(q.angle_between(Quat::from_rotation_y(std::f32::consts::FRAC_PI_4)) < 1e-4);
+    }
+
+    #[test]
+    fn joint_matrices_are_relative_to_the_skinned_node() {
+        let node = |parent, translation, deformers: Vec<usize>, skin| AnimationNode {
+            parent,
+            translation,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+            instances: 0..0,
+            camera: None,
+            deformers,
+            skin,
+            weights: vec![0.5],
+        };
+        // The skinned mesh sits at z = 5, its joint at x = 1 is turned a quarter around z.
+        let nodes = vec![
+            node(None, vec3(0., 0., 5.), vec![0], Some(0)),
+            node(None, vec3(1., 0., 0.), vec![], None),
+        ];
+        let skin = Skin {
+            joints: vec![1],
+            inverse_bind_matrices: vec![Mat4::from_translation(vec3(-1., 0., 0.))],
+        };
+        let rotation = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2).to_array();
+        let mut rotate = channel(Path::Rotation, Interpolation::Step, &[0.], &rotation);
+        rotate.node = 1;
+        let animation = Animation::new(nodes, vec![Clip::new(None, vec![rotate])], vec![skin]);
+
+        let mut scene = Scene::default();
+        scene.deformations.deformers.push(crate::deform::Deformer {
+            joint_count: 1,
+            target_count: 1,
+            ..Default::default()
+        });
+        scene.deformations.joint_matrices.push(Mat4::IDENTITY);
+        scene.deformations.morph_weights.push(0.);
+        animation.apply(0, 0., &mut scene);
+
+        // to_node * joint * inverse_bind: back to the joint, rotated, into the frame of the node.
+        let joint = scene.deformations.joint_matrices[0];
+        let p = joint.transform_point3(vec3(1., 0., 0.));
+        assert!(p.abs_diff_eq(vec3(1., 0., -5.), 1e-5), "{p}");
+        let p = joint.transform_point3(vec3(2., 0., 0.));
+        assert!(p.abs_diff_eq(vec3(1., 1., -5.), 1e-5), "{p}");
+        assert_eq!(scene.deformations.morph_weights, vec![0.5]);
+    }
+}
@@ -0,0 +1,295 @@
+//! CPU reference of the BSDFs in `src/shaders/path-tracing/bsdf`.
+//! Directions are given in the local shading frame where `z` is the normal.
+//! Only compiled for the tests, which check energy conservation and that `pdf` matches `sample`.
+use glam::*;
+use std::f32::consts::PI;
+
+fn luminance(c: Vec3) -> f32 {
+    0.21271 * c.x + 0.715160 * c.y + 0.072169 * c.z
+}
+
+pub fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1. - cos_theta).clamp(0., 1.).powi(5)
+}
+
+pub fn ggx_d(h: Vec3, alpha: f32) -> f32 {
+    if h.z <= 0. {
+        return 0.;
+    }
+    let a2 = alpha * alpha;
+    let d = h.z * h.z * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+pub fn ggx_g1(v: Vec3, alpha: f32) -> f32 {
+    let cos_theta = v.z.abs();
+    let a2 = alpha * alpha;
+    2. * cos_theta / (cos_theta + (a2 + (1. - a2) * cos_theta * cos_theta).sqrt())
+}
+
+pub fn ggx_g(wi: Vec3, wo: Vec3, alpha: f32) -> f32 {
+    ggx_g1(wi, alpha) * ggx_g1(wo, alpha)
+}
+
+///
+/// Samples the distribution of visible normals (Heitz 2018).
+///
+pub fn sample_ggx_vndf(wi: Vec3, alpha: f32, sample: Vec2) -> Vec3 {
+    let v = vec3(alpha * wi.x, alpha * wi.y, wi.z).normalize();
+
+    let lensq = v.x * v.x + v.y * v.y;
+    let t1 = if lensq > 0. {
+        vec3(-v.y, v.x, 0.) / lensq.sqrt()
+    } else {
+        Vec3::X
+    };
+    let t2 = v.cross(t1);
+
+    let r = sample.x.sqrt();
+    let phi = 2. * PI * sample.y;
+    let p1 = r * phi.cos();
+    let p2 = r * phi.sin();
+    let s = 0.5 * (1. + v.z);
+    let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * p2;
+
+    let n = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * v;
+
+    vec3(alpha * n.x, alpha * n.y, n.z.max(0.)).normalize()
+}
+
+pub fn ggx_vndf_pdf(wi: Vec3, h: Vec3, alpha: f32) -> f32 {
+    if wi.z <= 0. {
+        return 0.;
+    }
+    ggx_g1(wi, alpha) * wi.dot(h).max(0.) * ggx_d(h, alpha) / wi.z
+}
+
+fn square_to_uniform_disk_concentric(s: Vec2) -> Vec2 {
+    let x = 2. * s.x - 1.;
+    let y = 2. * s.y - 1.;
+    let (r, phi) = if x == 0. && y == 0. {
+        (0., 0.)
+    } else if x * x > y * y {
+        (x, (PI / 4.) * (y / x))
+    } else {
+        (y, (PI / 2.) - (x / y) * (PI / 4.))
+    };
+    vec2(r * phi.cos(), r * phi.sin())
+}
+
+pub fn square_to_cosine_hemisphere(s: Vec2) -> Vec3 {
+    let p = square_to_uniform_disk_concentric(s);
+    let z = (1. - p.dot(p)).max(0.).sqrt();
+    vec3(p.x, p.y, z)
+}
+
+pub fn square_to_cosine_hemisphere_pdf(v: Vec3) -> f32 {
+    if v.z > 0. {
+        v.z / PI
+    } else {
+        0.
+    }
+}
+
+pub struct BsdfSample {
+    pub wo: Vec3,
+    pub pdf: f32,
+    /// f(wi, wo) * cos_theta_o / pdf
+    pub value: Vec3,
+}
+
+///
+/// Mirrors `bsdf/principled.glsl`.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct PrincipledBsdf {
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl PrincipledBsdf {
+    fn alpha(&self) -> f32 {
+        (self.roughness * self.roughness).max(1e-3)
+    }
+    fn f0(&self) -> Vec3 {
+        Vec3::splat(0.04).lerp(self.base_color, self.metallic)
+    }
+    fn specular_prob(&self, cos_theta_i: f32) -> f32 {
+        let spec = luminance(fresnel_schlick(self.f0(), cos_theta_i));
+        let diff = luminance(self.base_color) * (1. - self.metallic);
+        if spec + diff <= 0. {
+            0.5
+        } else {
+            spec / (spec + diff)
+        }
+    }
+    ///
+    /// Evaluates f(wi, wo) * cos_theta_o.
+    ///
+    pub fn eval(&self, wi: Vec3, wo: Vec3) -> Vec3 {
+        if wi.z <= 0. || wo.z <= 0. {
+            return Vec3::ZERO;
+        }
+        let h = (wi + wo).normalize();
+        let f = fresnel_schlick(self.f0(), wi.dot(h));
+        let d = ggx_d(h, self.alpha());
+        let g = ggx_g(wi, wo, self.alpha());
+
+        let specular = f * d * g / (4. * wi.z * wo.z);
+        // Light reflected by the specular layer at either direction does not reach the diffuse
+        // base, weighting with the half vector instead gains energy at grazing angles.
+        let diffuse = (1. - self.metallic)
+            * (Vec3::ONE - fresnel_schlick(Vec3::splat(0.04), wi.z))
+            * (Vec3::ONE - fresnel_schlick(Vec3::splat(0.04), wo.z))
+            * self.base_color
+            / PI;
+
+        (diffuse + specular) * wo.z
+    }
+    pub fn pdf(&self, wi: Vec3, wo: Vec3) -> f32 {
+        if wi.z <= 0. || wo.z <= 0. {
+            return 0.;
+        }
+        let p_spec = self.specular_prob(wi.z);
+        let h = (wi + wo).normalize();
+        let pdf_spec = ggx_vndf_pdf(wi, h, self.alpha()) / (4. * wi.dot(h));
+        let pdf_diff = square_to_cosine_hemisphere_pdf(wo);
+        p_spec * pdf_spec + (1. - p_spec) * pdf_diff
+    }
+    pub fn sample(&self, wi: Vec3, sample1: f32, sample2: Vec2) -> Option<BsdfSample> {
+        if wi.z <= 0. {
+            return None;
+        }
+        let wo = if sample1 < self.specular_prob(wi.z) {
+            let h = sample_ggx_vndf(wi, self.alpha(), sample2);
+            // reflect(-wi, h)
+            2. * wi.dot(h) * h - wi
+        } else {
+            square_to_cosine_hemisphere(sample2)
+        };
+        let pdf = self.pdf(wi, wo);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            wo,
+            pdf,
+            value: self.eval(wi, wo) / pdf,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Small xorshift generator, tests have to be deterministic.
+    ///
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+        fn next2(&mut self) -> Vec2 {
+            vec2(self.next(), self.next())
+        }
+    }
+
+    fn direction(cos_theta: f32) -> Vec3 {
+        vec3((1. - cos_theta * cos_theta).sqrt(), 0., cos_theta)
+    }
+
+    fn bsdfs() -> Vec<PrincipledBsdf> {
+        let mut bsdfs = vec![];
+        for metallic in [0., 1.] {
+            for roughness in [0.2, 0.5, 1.] {
+                bsdfs.push(PrincipledBsdf {
+                    base_color: Vec3::ONE,
+                    metallic,
+                    roughness,
+                });
+            }
+        }
+        bsdfs
+    }
+
+    #[test]
+    fn white_furnace() {
+        const N: usize = 100_000;
+        let mut rng = Rng(1);
+        for bsdf in bsdfs() {
+            for cos_theta in [0.1, 0.5, 0.9] {
+                let wi = direction(cos_theta);
+                let albedo = (0..N)
+                    .filter_map(|_| bsdf.sample(wi, rng.next(), rng.next2()))
+                    .map(|sample| sample.value)
+                    .sum::<Vec3>()
+                    / N as f32;
+                // A white BSDF must not create energy, single scattering GGX loses some.
+                assert!(
+                    albedo.max_element() <= 1.02 && albedo.min_element() > 0.3,
+                    "{bsdf:?} at cos_theta {cos_theta} has albedo {albedo}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_matches_sample_histogram() {
+        // Bins over (cos_theta, phi) of the upper hemisphere, both uniform in solid angle.
+        const COS_BINS: usize = 8;
+        const PHI_BINS: usize = 16;
+        const N: usize = 400_000;
+        let bin = |wo: Vec3| {
+            let phi = wo.y.atan2(wo.x).rem_euclid(2. * PI);
+            let i = ((wo.z * COS_BINS as f32) as usize).min(COS_BINS - 1);
+            let j = ((phi / (2. * PI) * PHI_BINS as f32) as usize).min(PHI_BINS - 1);
+            i * PHI_BINS + j
+        };
+
+        let mut rng = Rng(7);
+        for bsdf in bsdfs() {
+            let wi = direction(0.6);
+
+            let mut histogram = vec![0.; COS_BINS * PHI_BINS];
+            for _ in 0..N {
+                if let Some(sample) = bsdf.sample(wi, rng.next(), rng.next2()) {
+                    let expected = bsdf.eval(wi, sample.wo) / sample.pdf;
+                    assert!((sample.value - expected).abs().max_element() < 1e-4);
+                    histogram[bin(sample.wo)] += 1. / N as f32;
+                }
+            }
+
+            // Integrate the pdf over every bin with stratified points.
+            const STRATA: usize = 16;
+            let mut integral = vec![0.; COS_BINS * PHI_BINS];
+            for i in 0..COS_BINS * STRATA {
+                for j in 0..PHI_BINS * STRATA {
+                    let cos_theta = (i as f32 + 0.5) / (COS_BINS * STRATA) as f32;
+                    let phi = 2. * PI * (j as f32 + 0.5) / (PHI_BINS * STRATA) as f32;
+                    let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+                    let wo = vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                    let solid_angle = 2. * PI / (COS_BINS * PHI_BINS * STRATA * STRATA) as f32;
+                    integral[bin(wo)] += bsdf.pdf(wi, wo) * solid_angle;
+                }
+            }
+
+            // Microfacet samples reflected below the horizon are rejected, so the pdf may
+            // integrate to less than one.
+            let total = integral.iter().sum::<f32>();
+            assert!(total < 1.02, "{bsdf:?} pdf integrates to {total}");
+            for (k, (h, p)) in histogram.iter().zip(integral.iter()).enumerate() {
+                assert!(
+                    (h - p).abs() < 0.005 + 0.05 * p,
+                    "{bsdf:?} bin {k}: sampled {h}, pdf {p}"
+                );
+            }
+        }
+    }
+}
@@ -2,16 +2,22 @@ use crevice::std140::AsStd140;
 use glam::*;
 use macros::ReprGlsl;
 
+/// Same as `luminance` in spectrum.glsl.
+pub fn luminance(c: Vec3) -> f32 {
+    0.21271 * c.x + 0.715160 * c.y + 0.072169 * c.z
+}
+
 #[derive(AsStd140, Debug)]
 pub struct Mesh {
     pub indices: u32,
     pub indices_count: u32,
     pub positions: u32,
     pub normals: u32,
+    pub tangents: u32,
     pub uvs: u32,
 }
 
-#[derive(AsStd140, Debug)]
+#[derive(AsStd140, Debug, Default)]
 pub struct Instance {
     pub to_world: Mat4,
     pub mesh: u32,
@@ -21,32 +27,146 @@ pub struct Instance {
 
 #[derive(AsStd140, Debug)]
 pub struct Emitter {
+    /// Environment map, or the radiant intensity (irradiance for directional lights) of
+    /// punctual lights as a constant.
     pub irradiance: Texture,
     pub instance: u32,
     pub ty: u32,
+    pub position: Vec3,
+    /// Direction the light is pointing to.
+    pub direction: Vec3,
+    pub cos_inner_cone: f32,
+    pub cos_outer_cone: f32,
+    /// Offset of the triangle area CDF of area emitters, set by `Scene::upload`.
+    pub triangle_cdf: u32,
+    /// World space surface area of area emitters, set by `Scene::upload`.
+    pub area: f32,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            irradiance: Texture::default(),
+            instance: 0,
+            ty: Self::TY_NONE,
+            position: Vec3::ZERO,
+            direction: -Vec3::Z,
+            cos_inner_cone: -1.,
+            cos_outer_cone: -1.,
+            triangle_cdf: 0,
+            area: 0.,
+        }
+    }
 }
 
 impl Emitter {
-    const TY_NONE: u32 = 0;
-    const TY_ENV: u32 = 1;
-    const TY_AREA: u32 = 2;
+    pub const TY_NONE: u32 = 0;
+    pub const TY_ENV: u32 = 1;
+    pub const TY_AREA: u32 = 2;
+    pub const TY_POINT: u32 = 3;
+    pub const TY_SPOT: u32 = 4;
+    pub const TY_DIRECTIONAL: u32 = 5;
     pub fn env(irradiance: Texture) -> Self {
         Self {
             irradiance,
-            instance: 0,
             ty: Self::TY_ENV,
+            ..Default::default()
         }
     }
-    pub fn area(irradiance: Texture, instance: u32) -> Self {
+    pub fn is_env(&self) -> bool {
+        self.ty == Self::TY_ENV
+    }
+    ///
+    /// Infinitely far away emitters, which are not part of the light BVH.
+    ///
+    pub fn is_infinite(&self) -> bool {
+        self.ty == Self::TY_ENV || self.ty == Self::TY_DIRECTIONAL
+    }
+    ///
+    /// Area emitters take their radiance from the emission of the instance's material.
+    ///
+    pub fn area(instance: u32) -> Self {
         Self {
-            irradiance,
             instance,
             ty: Self::TY_AREA,
+            ..Default::default()
+        }
+    }
+    ///
+    /// `intensity` is the radiant intensity in W/sr.
+    ///
+    pub fn point(intensity: Vec3, position: Vec3) -> Self {
+        Self {
+            irradiance: Texture::constant(intensity),
+            ty: Self::TY_POINT,
+            position,
+            ..Default::default()
+        }
+    }
+    ///
+    /// Spot light with a smooth falloff between the inner and outer cone angle (in radians).
+    ///
+    pub fn spot(
+        intensity: Vec3,
+        position: Vec3,
+        direction: Vec3,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    ) -> Self {
+        Self {
+            irradiance: Texture::constant(intensity),
+            ty: Self::TY_SPOT,
+            position,
+            direction,
+            cos_inner_cone: inner_cone_angle.cos(),
+            cos_outer_cone: outer_cone_angle.cos(),
+            ..Default::default()
+        }
+    }
+    ///
+    /// `irradiance` in W/m² perpendicular to `direction`.
+    ///
+    pub fn directional(irradiance: Vec3, direction: Vec3) -> Self {
+        Self {
+            irradiance: Texture::constant(irradiance),
+            ty: Self::TY_DIRECTIONAL,
+            direction,
+            ..Default::default()
         }
     }
 }
 
-#[derive(AsStd140, Debug)]
+///
+/// Entry of an alias table, see `distribution::AliasTable`.
+///
+#[derive(AsStd140, Debug, Clone, Copy, Default)]
+pub struct AliasEntry {
+    /// Probability of keeping this entry instead of jumping to `alias`.
+    pub threshold: f32,
+    pub alias: u32,
+    /// Probability of sampling this entry.
+    /// For emitters in the light BVH the probability of traversing the BVH instead.
+    pub pdf: f32,
+}
+
+///
+/// Node of the light BVH, see `light_bvh::LightBvh`.
+///
+#[derive(AsStd140, Debug, Clone, Copy, Default)]
+pub struct LightBvhNode {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub axis: Vec3,
+    pub cos_theta_o: f32,
+    pub cos_theta_e: f32,
+    pub power: f32,
+    /// Second child of interior nodes, emitter of leaves.
+    pub index: u32,
+    pub leaf: u32,
+    pub two_sided: u32,
+}
+
+#[derive(AsStd140, Debug, Clone, Copy)]
 pub struct Texture {
     pub val: Vec3,
     pub texture: u32,
@@ -64,8 +184,8 @@ impl Default for Texture {
 }
 
 impl Texture {
-    const TY_CONSTANT: u32 = 0;
-    const TY_IMAGE: u32 = 1;
+    pub const TY_CONSTANT: u32 = 0;
+    pub const TY_IMAGE: u32 = 1;
     pub fn constant(val: Vec3) -> Self {
         Self {
             ty: Self::TY_CONSTANT,
@@ -82,12 +202,32 @@ impl Texture {
     }
 }
 
-#[derive(AsStd140, Debug, Default)]
+#[derive(AsStd140, Debug)]
 pub struct Material {
     pub normal: Texture,
     pub base_color: Texture,
     pub metallic_roughness: Texture,
     pub transmission: Texture,
+    pub ior: f32,
+    pub normal_scale: f32,
+    pub emission: Texture,
+    /// Emissive factor multiplied with the emissive strength, scales `emission`.
+    pub emission_factor: Vec3,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            normal: Texture::default(),
+            base_color: Texture::default(),
+            metallic_roughness: Texture::default(),
+            transmission: Texture::default(),
+            ior: 1.5,
+            normal_scale: 1.,
+            emission: Texture::constant(Vec3::ONE),
+            emission_factor: Vec3::ZERO,
+        }
+    }
 }
 
 #[derive(AsStd140, Debug)]
@@ -96,6 +236,7 @@ pub struct Camera {
     pub to_view: Mat4,
     pub near_clip: f32,
     pub far_clip: f32,
+    pub fov_y: f32,
 }
 
 impl Camera {
@@ -118,9 +259,19 @@ impl Camera {
             to_view,
             near_clip,
             far_clip,
+            fov_y,
             //size: glam::uvec2(width, height),
         }
     }
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        *self = Self::perspective(
+            self.to_world,
+            self.fov_y,
+            aspect_ratio,
+            self.near_clip,
+            self.far_clip,
+        );
+    }
 }
 
 // #[derive(AsStd140, Debug, Clone, Copy)]
@@ -0,0 +1,107 @@
+use glam::*;
+use std::collections::HashSet;
+use winit::event::{
+    DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+};
+
+///
+/// WASD + mouse-look camera controller.
+/// The mouse only rotates the camera while the right mouse button is held.
+///
+pub struct FlyCamera {
+    pub speed: f32,
+    pub sensitivity: f32,
+    held: HashSet<VirtualKeyCode>,
+    looking: bool,
+}
+
+impl Default for FlyCamera {
+    fn default() -> Self {
+        Self {
+            speed: 1.,
+            sensitivity: 0.005,
+            held: HashSet::new(),
+            looking: false,
+        }
+    }
+}
+
+impl FlyCamera {
+    ///
+    /// Applies the input of this frame to `to_world`.
+    /// Returns true if the camera has been moved.
+    ///
+    pub fn update(&mut self, events: &[Event<'_, ()>], dt: f32, to_world: &mut Mat4) -> bool {
+        let (scale, mut rotation, mut translation) = to_world.to_scale_rotation_translation();
+        let mut moved = false;
+
+        for event in events {
+            match event {
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    virtual_keycode: Some(key),
+                                    state,
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => match state {
+                    ElementState::Pressed => {
+                        self.held.insert(*key);
+                    }
+                    ElementState::Released => {
+                        self.held.remove(key);
+                    }
+                },
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::MouseInput {
+                            button: MouseButton::Right,
+                            state,
+                            ..
+                        },
+                    ..
+                } => {
+                    self.looking = *state == ElementState::Pressed;
+                }
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta },
+                    ..
+                } if self.looking => {
+                    let yaw = Quat::from_rotation_y(-delta.0 as f32 * self.sensitivity);
+                    let pitch = Quat::from_rotation_x(-delta.1 as f32 * self.sensitivity);
+                    rotation = (yaw * rotation * pitch).normalize();
+                    moved = true;
+                }
+                _ => {}
+            }
+        }
+
+        // Camera looks along -z (glTF convention).
+        let mut dir = Vec3::ZERO;
+        for key in self.held.iter() {
+            dir += match key {
+                VirtualKeyCode::W => -Vec3::Z,
+                VirtualKeyCode::S => Vec3::Z,
+                VirtualKeyCode::A => -Vec3::X,
+                VirtualKeyCode::D => Vec3::X,
+                VirtualKeyCode::E => Vec3::Y,
+                VirtualKeyCode::Q => -Vec3::Y,
+                _ => Vec3::ZERO,
+            };
+        }
+        if dir != Vec3::ZERO {
+            translation += rotation * dir.normalize() * self.speed * dt;
+            moved = true;
+        }
+
+        if moved {
+            *to_world = Mat4::from_scale_rotation_translation(scale, rotation, translation);
+        }
+        moved
+    }
+}
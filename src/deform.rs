@@ -0,0 +1,213 @@
+//! Skinning and morph targets, applied on the GPU before the BLASes of the deformed meshes are
+//! updated. The rest pose of every deformed mesh is kept in separate buffers, the compute pass in
+//! `src/shaders/util/deform.glsl` writes the posed vertices into the regions of the mesh in the
+//! scene buffers.
+use crevice::std140::{AsStd140, Std140, WriteStd140};
+use glam::*;
+use inline_spirv::include_spirv;
+use screen_13::prelude::*;
+use std::sync::Arc;
+
+use crate::array::Array;
+
+/// Same as `local_size_x` in deform.glsl.
+const WORKGROUP_SIZE: u32 = 64;
+
+///
+/// Joints and weights of a vertex of a skinned mesh, joints index into the joint matrices of
+/// the deformer.
+///
+#[derive(AsStd140, Debug, Clone, Copy, Default)]
+pub struct SkinVertex {
+    /// Joint indices, stored as floats since crevice has no integer vectors.
+    pub joints: Vec4,
+    pub weights: Vec4,
+}
+
+///
+/// Deformation of the vertices of one mesh, also used as push constant of the compute pass.
+///
+#[derive(AsStd140, Debug, Clone, Copy, Default)]
+pub struct Deformer {
+    pub mesh: u32,
+    /// Offsets of the mesh in the scene vertex buffers.
+    pub positions: u32,
+    pub normals: u32,
+    pub tangents: u32,
+    pub vertex_count: u32,
+    /// Offset into the rest pose and skin vertices.
+    pub rest: u32,
+    /// Offset into the joint matrices.
+    pub joints: u32,
+    /// Zero if the mesh is not skinned.
+    pub joint_count: u32,
+    /// Offset into the morph target deltas, which are stored target by target.
+    pub morph: u32,
+    /// Offset into the morph target weights.
+    pub weights: u32,
+    pub target_count: u32,
+}
+
+///
+/// Rest poses and current pose of all deformed meshes of a scene.
+///
+#[derive(Debug, Default)]
+pub struct Deformations {
+    pub deformers: Vec<Deformer>,
+    pub rest_positions: Vec<Vec3>,
+    pub rest_normals: Vec<Vec3>,
+    pub rest_tangents: Vec<Vec4>,
+    pub skin_vertices: Vec<SkinVertex>,
+    pub morph_positions: Vec<Vec3>,
+    pub morph_normals: Vec<Vec3>,
+    pub morph_tangents: Vec<Vec3>,
+    /// Joint matrices relative to the deformed mesh, set by `set_pose`.
+    pub joint_matrices: Vec<Mat4>,
+    pub morph_weights: Vec<f32>,
+}
+
+impl Deformations {
+    pub fn is_empty(&self) -> bool {
+        self.deformers.is_empty()
+    }
+    ///
+    /// Whether `mesh` is deformed, its BLAS has to allow updates.
+    ///
+    pub fn is_deformed(&self, mesh: usize) -> bool {
+        self.deformers
+            .iter()
+            .any(|deformer| deformer.mesh as usize == mesh)
+    }
+    ///
+    /// Sets the joint matrices and morph target weights of a deformer, missing values keep
+    /// their previous value.
+    ///
+    pub fn set_pose(&mut self, deformer: usize, joints: &[Mat4], weights: &[f32]) {
+        let deformer = &self.deformers[deformer];
+        let count = joints.len().min(deformer.joint_count as usize);
+        let start = deformer.joints as usize;
+        self.joint_matrices[start..start + count].copy_from_slice(&joints[..count]);
+
+        let count = weights.len().min(deformer.target_count as usize);
+        let start = deformer.weights as usize;
+        self.morph_weights[start..start + count].copy_from_slice(&weights[..count]);
+    }
+}
+
+///
+/// Compute pass applying the deformers, holds the rest pose on the GPU.
+///
+pub struct DeformPass {
+    ppl: Arc<ComputePipeline>,
+    rest_positions: Array<Vec3>,
+    rest_normals: Array<Vec3>,
+    rest_tangents: Array<Vec4>,
+    skin_vertices: Array<SkinVertex>,
+    morph_positions: Array<Vec3>,
+    morph_normals: Array<Vec3>,
+    morph_tangents: Array<Vec3>,
+}
+
+impl DeformPass {
+    pub fn new(
+        device: &Arc<Device>,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
+        deformations: &Deformations,
+    ) -> Self {
+        Self {
+            ppl: Arc::new(
+                ComputePipeline::create(
+                    device,
+                    ComputePipelineInfo::default(),
+                    Shader::new_compute(
+                        include_spirv!("src/shaders/util/deform.glsl", comp).as_slice(),
+                    ),
+                )
+                .unwrap(),
+            ),
+            rest_positions: storage(device, cache, rgraph, &deformations.rest_positions),
+            rest_normals: storage(device, cache, rgraph, &deformations.rest_normals),
+            rest_tangents: storage(device, cache, rgraph, &deformations.rest_tangents),
+            skin_vertices: storage(device, cache, rgraph, &deformations.skin_vertices),
+            morph_positions: storage(device, cache, rgraph, &deformations.morph_positions),
+            morph_normals: storage(device, cache, rgraph, &deformations.morph_normals),
+            morph_tangents: storage(device, cache, rgraph, &deformations.morph_tangents),
+        }
+    }
+    ///
+    /// Uploads the current pose and writes the deformed vertices of every deformer into the
+    /// scene vertex buffers.
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        device: &Arc<Device>,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
+        deformations: &Deformations,
+        positions: &Array<Vec3>,
+        normals: &Array<Vec3>,
+        tangents: &Array<Vec4>,
+    ) {
+        let joint_matrices = storage(device, cache, rgraph, &deformations.joint_matrices);
+        let morph_weights = storage(device, cache, rgraph, &deformations.morph_weights);
+
+        let positions = rgraph.bind_node(&positions.buf);
+        let normals = rgraph.bind_node(&normals.buf);
+        let tangents = rgraph.bind_node(&tangents.buf);
+        let rest_positions = rgraph.bind_node(&self.rest_positions.buf);
+        let rest_normals = rgraph.bind_node(&self.rest_normals.buf);
+        let rest_tangents = rgraph.bind_node(&self.rest_tangents.buf);
+        let skin_vertices = rgraph.bind_node(&self.skin_vertices.buf);
+        let joint_matrices = rgraph.bind_node(&joint_matrices.buf);
+        let morph_positions = rgraph.bind_node(&self.morph_positions.buf);
+        let morph_normals = rgraph.bind_node(&self.morph_normals.buf);
+        let morph_tangents = rgraph.bind_node(&self.morph_tangents.buf);
+        let morph_weights = rgraph.bind_node(&morph_weights.buf);
+
+        let deformers = deformations.deformers.clone();
+        rgraph
+            .begin_pass("Deform meshes")
+            .bind_pipeline(&self.ppl)
+            .write_descriptor((0, 0), positions)
+            .write_descriptor((0, 1), normals)
+            .write_descriptor((0, 2), tangents)
+            .read_descriptor((0, 3), rest_positions)
+            .read_descriptor((0, 4), rest_normals)
+            .read_descriptor((0, 5), rest_tangents)
+            .read_descriptor((0, 6), skin_vertices)
+            .read_descriptor((0, 7), joint_matrices)
+            .read_descriptor((0, 8), morph_positions)
+            .read_descriptor((0, 9), morph_normals)
+            .read_descriptor((0, 10), morph_tangents)
+            .read_descriptor((0, 11), morph_weights)
+            .record_compute(move |compute, _| {
+                for deformer in deformers.iter() {
+                    compute.push_constants(deformer.as_std140().as_bytes());
+                    compute.dispatch(
+                        (deformer.vertex_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                        1,
+                        1,
+                    );
+                }
+            });
+    }
+}
+
+///
+/// Uploads `data` as storage buffer, binds a dummy entry if it is empty.
+///
+fn storage<T: AsStd140 + WriteStd140 + Default>(
+    device: &Arc<Device>,
+    cache: &mut HashPool,
+    rgraph: &mut RenderGraph,
+    data: &[T],
+) -> Array<T> {
+    let usage = vk::BufferUsageFlags::STORAGE_BUFFER;
+    if data.is_empty() {
+        Array::from_slice_staging(device, cache, rgraph, usage, &[T::default()])
+    } else {
+        Array::from_slice_staging(device, cache, rgraph, usage, data)
+    }
+}
@@ -0,0 +1,351 @@
+//! Piecewise-constant distributions for importance sampling (pbrt-v3, section 13.3).
+//! Sampling and pdf evaluation are mirrored in `src/shaders/path-tracing/emitter.glsl`,
+//! which only receives the CDFs (see [`Distribution2D::cdfs`]).
+use crate::common::AliasEntry;
+#[cfg(test)]
+use glam::*;
+
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    pub func: Vec<f32>,
+    /// `func.len() + 1` entries, starting at 0 and ending at 1.
+    pub cdf: Vec<f32>,
+    /// Integral of `func` over [0, 1].
+    pub integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: &[f32]) -> Self {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.);
+        for i in 0..n {
+            cdf.push(cdf[i] + func[i].max(0.) / n as f32);
+        }
+        let integral = cdf[n];
+        if integral > 0. {
+            for c in cdf.iter_mut() {
+                *c /= integral;
+            }
+        } else {
+            // Fall back to a uniform distribution.
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        }
+        // Guard against rounding errors.
+        cdf[n] = 1.;
+        Self {
+            func: func.to_vec(),
+            cdf,
+            integral,
+        }
+    }
+}
+
+// Sampling happens on the GPU, the host versions are kept to test the CDFs against.
+#[cfg(test)]
+impl Distribution1D {
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+    ///
+    /// Returns the index `i` of the segment with `cdf[i] <= u < cdf[i + 1]`.
+    ///
+    fn find_segment(&self, u: f32) -> usize {
+        let i = self.cdf.partition_point(|&c| c <= u);
+        i.clamp(1, self.count()) - 1
+    }
+    ///
+    /// Samples a continuous position in [0, 1) and returns it with its pdf and segment index.
+    ///
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let i = self.find_segment(u);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0. {
+            (u - self.cdf[i]) / width
+        } else {
+            0.
+        };
+        let x = (i as f32 + du) / self.count() as f32;
+        (x, width * self.count() as f32, i)
+    }
+    pub fn pdf(&self, x: f32) -> f32 {
+        let n = self.count();
+        let i = ((x * n as f32) as usize).min(n - 1);
+        (self.cdf[i + 1] - self.cdf[i]) * n as f32
+    }
+}
+
+///
+/// Distribution over [0, 1]² given by a row major `width` x `height` grid, rows are sampled
+/// with the marginal distribution and columns with the conditional distribution of each row.
+///
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    pub conditional: Vec<Distribution1D>,
+    pub marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        assert_eq!(func.len(), width * height);
+        let conditional = func
+            .chunks_exact(width)
+            .map(Distribution1D::new)
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(
+            &conditional
+                .iter()
+                .map(|row| row.integral)
+                .collect::<Vec<_>>(),
+        );
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+    ///
+    /// Flattens the CDFs for upload: the `height` conditional CDFs (`width + 1` entries each)
+    /// followed by the marginal CDF (`height + 1` entries).
+    ///
+    pub fn cdfs(&self) -> Vec<f32> {
+        self.conditional
+            .iter()
+            .chain(std::iter::once(&self.marginal))
+            .flat_map(|d| d.cdf.iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Distribution2D {
+    pub fn width(&self) -> usize {
+        self.conditional.first().map_or(0, |row| row.count())
+    }
+    pub fn height(&self) -> usize {
+        self.marginal.count()
+    }
+    ///
+    /// Returns the sampled position and its pdf with respect to [0, 1]².
+    ///
+    pub fn sample(&self, u: Vec2) -> (Vec2, f32) {
+        let (y, pdf_y, row) = self.marginal.sample(u.y);
+        let (x, pdf_x, _) = self.conditional[row].sample(u.x);
+        (vec2(x, y), pdf_x * pdf_y)
+    }
+    pub fn pdf(&self, p: Vec2) -> f32 {
+        let row = ((p.y * self.height() as f32) as usize).min(self.height() - 1);
+        self.marginal.pdf(p.y) * self.conditional[row].pdf(p.x)
+    }
+}
+
+///
+/// Alias table (Vose's method) for sampling a discrete distribution in constant time.
+///
+#[derive(Debug, Clone)]
+pub struct AliasTable {
+    pub entries: Vec<AliasEntry>,
+}
+
+impl AliasTable {
+    ///
+    /// Builds the table for the (unnormalized) `weights`, falls back to a uniform distribution
+    /// if all weights are zero.
+    ///
+    pub fn new(weights: &[f32]) -> Self {
+        let n = weights.len();
+        let sum = weights.iter().map(|w| w.max(0.)).sum::<f32>();
+        let pdf = |w: f32| {
+            if sum > 0. {
+                w.max(0.) / sum
+            } else {
+                1. / n as f32
+            }
+        };
+
+        let mut entries = weights
+            .iter()
+            .enumerate()
+            .map(|(i, &w)| AliasEntry {
+                threshold: 1.,
+                alias: i as u32,
+                pdf: pdf(w),
+            })
+            .collect::<Vec<_>>();
+
+        // Probabilities scaled such that the average is 1.
+        let mut scaled = entries.iter().map(|e| e.pdf * n as f32).collect::<Vec<_>>();
+        let (mut small, mut large): (Vec<_>, Vec<_>) = (0..n).partition(|&i| scaled[i] < 1.);
+
+        while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+            entries[s].threshold = scaled[s];
+            entries[s].alias = l as u32;
+
+            scaled[l] -= 1. - scaled[s];
+            if scaled[l] < 1. {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // Remaining entries are 1 up to rounding errors.
+        for i in small.into_iter().chain(large) {
+            entries[i].threshold = 1.;
+            entries[i].alias = i as u32;
+        }
+
+        Self { entries }
+    }
+}
+
+#[cfg(test)]
+impl AliasTable {
+    ///
+    /// Returns the sampled index and its probability.
+    ///
+    pub fn sample(&self, u: f32) -> (usize, f32) {
+        let n = self.entries.len();
+        let scaled = u * n as f32;
+        let i = (scaled as usize).min(n - 1);
+        let u = scaled - i as f32;
+
+        let entry = &self.entries[i];
+        let i = if u < entry.threshold {
+            i
+        } else {
+            entry.alias as usize
+        };
+        (i, self.entries[i].pdf)
+    }
+    pub fn pdf(&self, i: usize) -> f32 {
+        self.entries[i].pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_cdf(cdf: &[f32]) {
+        assert_eq!(cdf[0], 0.);
+        assert_eq!(*cdf.last().unwrap(), 1.);
+        assert!(
+            cdf.windows(2).all(|c| c[0] <= c[1]),
+            "{cdf:?} is not monotonic"
+        );
+    }
+
+    ///
+    /// Probability of every index implied by the thresholds and aliases.
+    ///
+    fn alias_probabilities(table: &AliasTable) -> Vec<f32> {
+        let n = table.entries.len();
+        let mut p = vec![0.; n];
+        for (i, entry) in table.entries.iter().enumerate() {
+            p[i] += entry.threshold / n as f32;
+            p[entry.alias as usize] += (1. - entry.threshold) / n as f32;
+        }
+        p
+    }
+
+    fn assert_alias_table(weights: &[f32], expected: &[f32]) {
+        let table = AliasTable::new(weights);
+        assert_eq!(table.entries.len(), weights.len());
+        for (i, (p, expected)) in alias_probabilities(&table)
+            .into_iter()
+            .zip(expected)
+            .enumerate()
+        {
+            assert!((p - expected).abs() < 1e-5, "{weights:?}: p[{i}] is {p}");
+            assert!((table.pdf(i) - expected).abs() < 1e-5);
+        }
+        // Entries of zero weight are never sampled.
+        for k in 0..1000 {
+            let (i, pdf) = table.sample((k as f32 + 0.5) / 1000.);
+            assert!(expected[i] > 0. && pdf == table.pdf(i));
+        }
+    }
+
+    #[test]
+    fn alias_table_reproduces_weights() {
+        assert_alias_table(&[1., 2., 3., 4.], &[0.1, 0.2, 0.3, 0.4]);
+        assert_alias_table(&[0., 5., 0., 15.], &[0., 0.25, 0., 0.75]);
+        assert_alias_table(
+            &[7.5, 0.5, 0.5, 0.5, 0.5, 0.5],
+            &[0.75, 0.05, 0.05, 0.05, 0.05, 0.05],
+        );
+        assert_alias_table(&[3.], &[1.]);
+        // All zero falls back to uniform.
+        assert_alias_table(&[0., 0.], &[0.5, 0.5]);
+    }
+
+    #[test]
+    fn cdf_ends_at_one() {
+        assert_cdf(&Distribution1D::new(&[1., 2., 0., 5.]).cdf);
+        assert_cdf(&Distribution1D::new(&[0.1; 7]).cdf);
+        // All black falls back to uniform.
+        let black = Distribution1D::new(&[0.; 4]);
+        assert_cdf(&black.cdf);
+        assert_eq!(black.cdf, vec![0., 0.25, 0.5, 0.75, 1.]);
+    }
+
+    #[test]
+    fn cdfs_end_at_one_with_black_rows() {
+        #[rustfmt::skip]
+        let func = [
+            1., 2., 3.,
+            0., 0., 0.,
+            4., 0., 1.,
+        ];
+        let distribution = Distribution2D::new(&func, 3, 3);
+        let cdfs = distribution.cdfs();
+        assert_eq!(cdfs.len(), 3 * 4 + 4);
+        for cdf in cdfs.chunks_exact(4) {
+            assert_cdf(cdf);
+        }
+
+        let black = Distribution2D::new(&[0.; 6], 3, 2);
+        for cdf in black.conditional.iter().map(|row| &row.cdf) {
+            assert_cdf(cdf);
+        }
+        assert_cdf(&black.marginal.cdf);
+    }
+
+    #[test]
+    fn sample_matches_pdf() {
+        #[rustfmt::skip]
+        let func = [
+            1., 2., 3., 0.,
+            0., 0., 0., 0.,
+            4., 0., 1., 8.,
+        ];
+        let distribution = Distribution2D::new(&func, 4, 3);
+        assert_eq!((distribution.width(), distribution.height()), (4, 3));
+
+        // The pdf is proportional to the function and integrates to one.
+        let integral = func.iter().sum::<f32>() / func.len() as f32;
+        for y in 0..3 {
+            for x in 0..4 {
+                let p = vec2((x as f32 + 0.5) / 4., (y as f32 + 0.5) / 3.);
+                let expected = func[y * 4 + x] / integral;
+                assert!((distribution.pdf(p) - expected).abs() < 1e-4);
+            }
+        }
+
+        // Sampled positions report the pdf evaluated at them and never land in black cells.
+        const N: usize = 64;
+        for i in 0..N {
+            for j in 0..N {
+                let u = vec2((i as f32 + 0.5) / N as f32, (j as f32 + 0.5) / N as f32);
+                let (p, pdf) = distribution.sample(u);
+                assert!(p.cmpge(Vec2::ZERO).all() && p.cmplt(Vec2::ONE).all());
+                assert!(pdf > 0.);
+                assert!(
+                    (pdf - distribution.pdf(p)).abs() < 1e-3 * pdf,
+                    "sample {u} at {p} has pdf {pdf}, pdf() is {}",
+                    distribution.pdf(p)
+                );
+            }
+        }
+    }
+}
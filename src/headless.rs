@@ -0,0 +1,164 @@
+use crate::post::Denoiser;
+use crate::renderer::Renderer;
+use crate::scene::Scene;
+use crate::settings::Settings;
+use anyhow::{anyhow, Result};
+use screen_13::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+const DEFAULT_SPP: u32 = 256;
+const MIB: f64 = (1 << 20) as f64;
+
+///
+/// Renders the scene without a window and writes the accumulated image as linear HDR (OpenEXR)
+/// next to a tonemapped sRGB PNG.
+///
+pub fn render(
+    device: &Arc<Device>,
+    mut scene: Scene,
+    output: &Path,
+    settings: &Settings,
+) -> Result<()> {
+    let mut cache = HashPool::new(device);
+
+    scene.set_aspect_ratio(settings.width as f32 / settings.height as f32);
+
+    let mut renderer = Renderer::new(device, settings);
+    let denoiser = Denoiser::new(device, settings.width, settings.height);
+
+    for i in 0..settings.spp.unwrap_or(DEFAULT_SPP) {
+        let mut rgraph = RenderGraph::new();
+        if i == 0 {
+            scene.update(device, &mut cache, &mut rgraph);
+        } else if let Some((before, after)) = scene.compact_blases(device, &mut cache, &mut rgraph)
+        {
+            // Every graph is waited on, the queries recorded by the first one are available.
+            eprintln!(
+                "Compacted BLAS memory from {:.1} MiB to {:.1} MiB",
+                before as f64 / MIB,
+                after as f64 / MIB
+            );
+        }
+        let scene = scene.bind(&mut rgraph);
+
+        let color = renderer.bind_and_render(
+            &scene,
+            settings.seed.wrapping_add(i),
+            settings,
+            &mut cache,
+            &mut rgraph,
+        );
+        denoiser.denoise(color, i, &mut rgraph);
+
+        rgraph
+            .resolve()
+            .submit(&mut cache, 0, 0)?
+            .wait_until_executed()?;
+    }
+
+    save(device, &mut cache, &denoiser, output, settings)
+}
+
+///
+/// Writes the image accumulated by the denoiser to `output` as .exr and .png.
+///
+pub fn save(
+    device: &Arc<Device>,
+    cache: &mut HashPool,
+    denoiser: &Denoiser,
+    output: &Path,
+    settings: &Settings,
+) -> Result<()> {
+    let (width, height) = (settings.width, settings.height);
+    let pixels = download(device, cache, denoiser, width, height)?;
+
+    write_exr(&output.with_extension("exr"), width, height, &pixels)?;
+    write_png(
+        &output.with_extension("png"),
+        width,
+        height,
+        settings.exposure,
+        &pixels,
+    )?;
+
+    Ok(())
+}
+
+///
+/// Copies the accumulated image of the denoiser into host memory as RGBA32F.
+///
+pub fn download(
+    device: &Arc<Device>,
+    cache: &mut HashPool,
+    denoiser: &Denoiser,
+    width: u32,
+    height: u32,
+) -> Result<Vec<f32>> {
+    let size = (width * height * 4) as usize * std::mem::size_of::<f32>();
+    let buf = Arc::new(Buffer::create(
+        device,
+        BufferInfo::new_mappable(size as _, vk::BufferUsageFlags::TRANSFER_DST),
+    )?);
+
+    let mut rgraph = RenderGraph::new();
+    let img_node = rgraph.bind_node(denoiser.image());
+    let buf_node = rgraph.bind_node(&buf);
+    rgraph.copy_image_to_buffer(img_node, buf_node);
+    rgraph
+        .resolve()
+        .submit(cache, 0, 0)?
+        .wait_until_executed()?;
+
+    Ok(Buffer::mapped_slice(&buf)[..size]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+pub fn write_exr(path: &Path, width: u32, height: u32, pixels: &[f32]) -> Result<()> {
+    let pixels = pixels
+        .chunks_exact(4)
+        .flat_map(|p| [p[0], p[1], p[2], 1.])
+        .collect::<Vec<_>>();
+    let img = image::Rgba32FImage::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow!("Image size does not match {width}x{height}"))?;
+    img.save(path)?;
+    Ok(())
+}
+
+pub fn write_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    exposure: f32,
+    pixels: &[f32],
+) -> Result<()> {
+    let scale = exposure.exp2();
+    let pixels = pixels
+        .chunks_exact(4)
+        .flat_map(|p| {
+            [
+                linear_to_srgb(p[0] * scale),
+                linear_to_srgb(p[1] * scale),
+                linear_to_srgb(p[2] * scale),
+                255,
+            ]
+        })
+        .collect::<Vec<_>>();
+    let img = image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow!("Image size does not match {width}x{height}"))?;
+    img.save(path)?;
+    Ok(())
+}
+
+// Same conversion as in linear_to_srgb_frag.glsl
+fn linear_to_srgb(linear: f32) -> u8 {
+    let linear = linear.clamp(0., 1.);
+    let srgb = if linear < 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    };
+    (srgb * 255.).round() as u8
+}
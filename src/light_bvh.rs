@@ -0,0 +1,397 @@
+//! Light BVH for sampling emitters proportional to their estimated contribution at a shading
+//! point (Conty Estevez and Kulla 2018, pbrt-v4 `BVHLightSampler`).
+//! Only emitters with a position are part of the BVH, infinite emitters (environment and
+//! directional lights) are selected with the alias table in `Scene::upload`.
+//! Traversal is implemented in `src/shaders/path-tracing/light-bvh.glsl`.
+use crate::common::LightBvhNode;
+use glam::*;
+use std::f32::consts::PI;
+
+///
+/// Cone of directions around `axis`, an empty cone has `cos_theta` of `f32::INFINITY`.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionCone {
+    pub axis: Vec3,
+    pub cos_theta: f32,
+}
+
+impl DirectionCone {
+    pub const EMPTY: Self = Self {
+        axis: Vec3::Z,
+        cos_theta: f32::INFINITY,
+    };
+    pub const SPHERE: Self = Self {
+        axis: Vec3::Z,
+        cos_theta: -1.,
+    };
+    pub fn new(axis: Vec3, cos_theta: f32) -> Self {
+        Self {
+            axis: axis.normalize(),
+            cos_theta,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cos_theta == f32::INFINITY
+    }
+    ///
+    /// Smallest cone containing both cones.
+    ///
+    pub fn union(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let theta_a = self.cos_theta.clamp(-1., 1.).acos();
+        let theta_b = other.cos_theta.clamp(-1., 1.).acos();
+        let theta_d = self.axis.angle_between(other.axis);
+
+        // One cone contains the other.
+        if (theta_d + theta_b).min(PI) <= theta_a {
+            return *self;
+        }
+        if (theta_d + theta_a).min(PI) <= theta_b {
+            return *other;
+        }
+
+        let theta_o = (theta_a + theta_d + theta_b) / 2.;
+        if theta_o >= PI {
+            return Self::SPHERE;
+        }
+        let theta_r = theta_o - theta_a;
+        let axis_r = self.axis.cross(other.axis);
+        if axis_r.length_squared() == 0. {
+            return Self::SPHERE;
+        }
+        let axis = Quat::from_axis_angle(axis_r.normalize(), theta_r) * self.axis;
+        Self::new(axis, theta_o.cos())
+    }
+}
+
+///
+/// Spatial and directional bounds of one or more emitters.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct LightBounds {
+    pub min: Vec3,
+    pub max: Vec3,
+    /// Bounds the normals (or the direction of spot lights).
+    pub normals: DirectionCone,
+    /// Cosine of the angle around the normals in which light is emitted.
+    pub cos_theta_e: f32,
+    pub power: f32,
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) / 2.
+    }
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            normals: self.normals.union(&other.normals),
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            power: self.power + other.power,
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+}
+
+impl LightBvhNode {
+    fn new(bounds: &LightBounds, index: usize, leaf: bool) -> Self {
+        Self {
+            min: bounds.min,
+            max: bounds.max,
+            axis: bounds.normals.axis,
+            cos_theta_o: bounds.normals.cos_theta,
+            cos_theta_e: bounds.cos_theta_e,
+            power: bounds.power,
+            index: index as u32,
+            leaf: leaf as u32,
+            two_sided: bounds.two_sided as u32,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LightBvh {
+    /// Nodes in depth first order, the first child of an interior node directly follows it.
+    pub nodes: Vec<LightBvhNode>,
+    /// Path from the root to the leaf of every emitter, bit `i` is set if the second child is
+    /// taken at depth `i`.
+    pub bit_trails: Vec<u32>,
+}
+
+impl LightBvh {
+    ///
+    /// Builds the BVH over all emitters that have bounds, `bounds` is indexed by emitter.
+    ///
+    pub fn new(bounds: &[Option<LightBounds>]) -> Self {
+        let mut lights = bounds
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bounds)| Some((i, (*bounds)?)))
+            .collect::<Vec<_>>();
+
+        let mut bvh = Self {
+            nodes: vec![],
+            bit_trails: vec![0; bounds.len()],
+        };
+        if !lights.is_empty() {
+            bvh.build(&mut lights, 0, 0);
+        }
+        bvh
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    ///
+    /// Splits at the median centroid along the largest axis, which keeps the depth below the
+    /// 32 bits available for the bit trails.
+    ///
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        bit_trail: u32,
+        depth: u32,
+    ) -> LightBounds {
+        if let [(emitter, bounds)] = lights {
+            self.nodes.push(LightBvhNode::new(bounds, *emitter, true));
+            self.bit_trails[*emitter] = bit_trail;
+            return *bounds;
+        }
+        assert!(depth < 32, "Light BVH is too deep for the bit trails");
+
+        let (min, max) = lights.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), (_, bounds)| (min.min(bounds.centroid()), max.max(bounds.centroid())),
+        );
+        let extent = max - min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        lights.sort_unstable_by(|(_, a), (_, b)| a.centroid()[axis].total_cmp(&b.centroid()[axis]));
+        let mid = lights.len() / 2;
+
+        let node = self.nodes.len();
+        self.nodes.push(LightBvhNode::default());
+
+        let (first, second) = lights.split_at_mut(mid);
+        let first = self.build(first, bit_trail, depth + 1);
+        let second_idx = self.nodes.len();
+        let second = self.build(second, bit_trail | (1 << depth), depth + 1);
+
+        let bounds = first.union(&second);
+        self.nodes[node] = LightBvhNode::new(&bounds, second_idx, false);
+        bounds
+    }
+}
+
+// Traversal happens on the GPU, the host version is kept to test the bit trails against.
+#[cfg(test)]
+impl LightBvhNode {
+    ///
+    /// Same as `light_bvh_importance` in light-bvh.glsl.
+    ///
+    fn importance(&self, p: Vec3) -> f32 {
+        let cos_sub_clamped = |sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32| {
+            if cos_a > cos_b {
+                1.
+            } else {
+                cos_a * cos_b + sin_a * sin_b
+            }
+        };
+        let sin_sub_clamped = |sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32| {
+            if cos_a > cos_b {
+                0.
+            } else {
+                sin_a * cos_b - cos_a * sin_b
+            }
+        };
+        let sin = |cos: f32| (1. - cos * cos).max(0.).sqrt();
+
+        let pc = (self.min + self.max) / 2.;
+        let dist2 = p.distance_squared(pc);
+        let d2 = dist2.max(self.min.distance(self.max) / 2.);
+        let wi = if dist2 > 0. {
+            (p - pc) / dist2.sqrt()
+        } else {
+            Vec3::Z
+        };
+        let mut cos_theta_w = self.axis.dot(wi);
+        if self.two_sided != 0 {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let radius2 = self.max.distance_squared(pc);
+        let cos_theta_b = if dist2 < radius2 {
+            -1.
+        } else {
+            (1. - radius2 / dist2).max(0.).sqrt()
+        };
+        let (sin_theta_w, sin_theta_b) = (sin(cos_theta_w), sin(cos_theta_b));
+        let (cos_theta_o, sin_theta_o) = (self.cos_theta_o, sin(self.cos_theta_o));
+
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.;
+        }
+        self.power * cos_theta_p / d2
+    }
+}
+
+#[cfg(test)]
+impl LightBvh {
+    ///
+    /// Same as `pdf_light_bvh` in light-bvh.glsl.
+    ///
+    fn pdf(&self, emitter: usize, p: Vec3) -> f32 {
+        let mut trail = self.bit_trails[emitter];
+        let mut node_idx = 0;
+        let mut pdf = 1.;
+        if self.nodes[0].leaf != 0 && self.nodes[0].importance(p) <= 0. {
+            return 0.;
+        }
+        while self.nodes[node_idx].leaf == 0 {
+            let node = &self.nodes[node_idx];
+            let first = self.nodes[node_idx + 1].importance(p);
+            let second = self.nodes[node.index as usize].importance(p);
+            if first + second <= 0. {
+                return 0.;
+            }
+            let p_first = first / (first + second);
+            if trail & 1 == 0 {
+                pdf *= p_first;
+                node_idx += 1;
+            } else {
+                pdf *= 1. - p_first;
+                node_idx = node.index as usize;
+            }
+            trail >>= 1;
+        }
+        pdf
+    }
+    ///
+    /// Index of the leaf reached by following the bit trail of `emitter`.
+    ///
+    fn leaf(&self, emitter: usize) -> usize {
+        let mut trail = self.bit_trails[emitter];
+        let mut node_idx = 0;
+        while self.nodes[node_idx].leaf == 0 {
+            node_idx = if trail & 1 == 0 {
+                node_idx + 1
+            } else {
+                self.nodes[node_idx].index as usize
+            };
+            trail >>= 1;
+        }
+        node_idx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Two sided emitters around a grid with varying power and normals, every third emitter is
+    /// unbounded like an environment map.
+    ///
+    fn emitter_bounds() -> Vec<Option<LightBounds>> {
+        (0..23)
+            .map(|i| {
+                if i % 3 == 2 {
+                    return None;
+                }
+                let position = vec3((i % 5) as f32, (i / 5) as f32, (i % 2) as f32 * 0.5);
+                let normal = vec3((i as f32).sin(), (i as f32).cos(), 0.5);
+                Some(LightBounds {
+                    min: position - 0.1,
+                    max: position + 0.1,
+                    normals: DirectionCone::new(normal, (i as f32 * 0.3).cos()),
+                    cos_theta_e: 0.,
+                    power: 1. + i as f32,
+                    two_sided: true,
+                })
+            })
+            .collect()
+    }
+
+    fn assert_contains(outer: &DirectionCone, inner: &DirectionCone) {
+        let theta_outer = outer.cos_theta.clamp(-1., 1.).acos();
+        let theta_inner = inner.cos_theta.clamp(-1., 1.).acos();
+        assert!(
+            (outer.axis.angle_between(inner.axis) + theta_inner).min(PI) <= theta_outer + 1e-4,
+            "{outer:?} does not contain {inner:?}"
+        );
+    }
+
+    #[test]
+    fn cone_union_contains_both_cones() {
+        let cones = (0..12)
+            .map(|i| {
+                let i = i as f32;
+                DirectionCone::new(vec3(i.sin(), (2. * i).cos(), i.cos()), (0.7 * i).cos())
+            })
+            .collect::<Vec<_>>();
+        for a in cones.iter() {
+            for b in cones.iter() {
+                let union = a.union(b);
+                assert_contains(&union, a);
+                assert_contains(&union, b);
+            }
+            assert_eq!(a.union(&DirectionCone::EMPTY).cos_theta, a.cos_theta);
+            assert_eq!(DirectionCone::EMPTY.union(a).cos_theta, a.cos_theta);
+        }
+        // Opposite axes.
+        let union = DirectionCone::new(Vec3::X, 0.9).union(&DirectionCone::new(-Vec3::X, 0.9));
+        assert_contains(&union, &DirectionCone::new(Vec3::X, 0.9));
+        assert_contains(&union, &DirectionCone::new(-Vec3::X, 0.9));
+    }
+
+    #[test]
+    fn bit_trails_lead_to_own_leaf() {
+        let bounds = emitter_bounds();
+        let bvh = LightBvh::new(&bounds);
+        let leaves = bvh.nodes.iter().filter(|node| node.leaf != 0).count();
+        assert_eq!(leaves, bounds.iter().flatten().count());
+        for (emitter, bounds) in bounds.iter().enumerate() {
+            if bounds.is_some() {
+                let leaf = &bvh.nodes[bvh.leaf(emitter)];
+                assert_eq!(leaf.index as usize, emitter);
+            }
+        }
+
+        let single = LightBvh::new(&[None, bounds[0]]);
+        assert_eq!(single.nodes.len(), 1);
+        assert_eq!(single.leaf(1), 0);
+        assert!(LightBvh::new(&[None, None]).is_empty());
+    }
+
+    #[test]
+    fn traversal_pmf_sums_to_one() {
+        let bounds = emitter_bounds();
+        let bvh = LightBvh::new(&bounds);
+        for p in [
+            vec3(2., 2., 3.),
+            vec3(-4., 1., 0.2),
+            vec3(1., 1., 0.),
+            vec3(10., -3., -5.),
+        ] {
+            let total = (0..bounds.len())
+                .filter(|&emitter| bounds[emitter].is_some())
+                .map(|emitter| bvh.pdf(emitter, p))
+                .sum::<f32>();
+            assert!((total - 1.).abs() < 1e-4, "pmf at {p} sums to {total}");
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::common::*;
+use crate::distribution::Distribution2D;
+use crate::scene::Scene;
+use anyhow::{Context, Result};
+use glam::*;
+use std::f32::consts::PI;
+use std::path::Path;
+
+use super::Loader;
+
+///
+/// Loads an equirectangular HDR image (.hdr, .exr) as the environment of the scene.
+/// The image is importance sampled proportional to its luminance.
+///
+#[derive(Default)]
+pub struct EnvMapLoader {}
+
+impl Loader<Scene> for EnvMapLoader {
+    fn append(&self, path: impl AsRef<Path>, dst: &mut Scene) -> Result<usize> {
+        let path = path.as_ref();
+        let img = image::open(path)
+            .with_context(|| format!("Failed to load environment map {}", path.display()))?
+            .into_rgba32f();
+        append_env(img, dst);
+        Ok(0)
+    }
+}
+
+///
+/// Sets an equirectangular image as the environment of the scene.
+///
+pub(super) fn append_env(img: image::Rgba32FImage, dst: &mut Scene) {
+    let (width, height) = img.dimensions();
+
+    // Rows near the poles cover less solid angle, sin(theta) compensates for that.
+    let func = img
+        .rows()
+        .enumerate()
+        .flat_map(|(y, row)| {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            row.map(move |p| luminance(vec3(p[0], p[1], p[2])) * sin_theta)
+        })
+        .collect::<Vec<_>>();
+    let distribution = Distribution2D::new(&func, width as usize, height as usize);
+
+    let texture = dst.textures.len();
+    dst.textures.push(image::DynamicImage::ImageRgba32F(img));
+    dst.set_env(
+        Emitter::env(Texture::image(texture as u32)),
+        distribution.cdfs(),
+    );
+}
@@ -1,119 +1,276 @@
+use crate::animation::{
+    Animation, AnimationNode, Channel, Clip, Interpolation, Path as AnimationPath, Skin,
+};
 use crate::common::*;
+use crate::deform::{Deformer, SkinVertex};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use glam::*;
-use screen_13_fx::ImageLoader;
+use gltf::animation::util::ReadOutputs;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
 use crate::scene::Scene;
 
-use super::Loader;
+use super::tangents::generate_tangents;
+use super::{flat_normals, unweld, Loader};
+
+/// Luminous efficacy used to convert the photometric units of KHR_lights_punctual
+/// (candela, lux) into radiometric ones (W/sr, W/m²).
+const LUMENS_PER_WATT: f32 = 683.;
 
 #[derive(Default)]
 pub struct GltfLoader {}
 
 impl Loader<Scene> for GltfLoader {
-    fn append(&self, path: impl AsRef<Path>, dst: &mut Scene) -> usize {
+    fn append(&self, path: impl AsRef<Path>, dst: &mut Scene) -> Result<usize> {
         let path = path.as_ref();
-        let (gltf, buffers, _) = gltf::import(path).unwrap();
+        // Also handles binary glTF (.glb), data URIs and images embedded in buffer views.
+        let (gltf, buffers, images) =
+            gltf::import(path).with_context(|| format!("Failed to import {}", path.display()))?;
 
         let texture_offset = dst.textures.len();
         for texture in gltf.textures() {
-            let img = match texture.source().source() {
-                gltf::image::Source::Uri { uri, mime_type } => {
-                    let parent = Path::new(path).parent().unwrap();
-                    let img_path = parent.join(uri);
-                    let img = image::io::Reader::open(img_path)
-                        .unwrap()
-                        .decode()
-                        .unwrap()
-                        .into_rgba8();
-                    image::DynamicImage::ImageRgba8(img)
-                }
-                _ => unimplemented!(),
-            };
-            dst.textures.push(img);
+            let img = to_dynamic_image(&images[texture.source().index()]).with_context(|| {
+                format!(
+                    "Failed to load texture {} of {}",
+                    texture.index(),
+                    path.display()
+                )
+            })?;
+            dst.textures
+                .push(image::DynamicImage::ImageRgba8(img.into_rgba8()));
         }
 
-        let mesh_offset = dst.meshes.len();
+        // Every primitive becomes its own mesh, the primitives of a glTF mesh are consecutive.
+        let mut primitive_meshes = vec![];
+        // Deformer of every skinned or morphed mesh.
+        let mut mesh_deformers = HashMap::new();
         for mesh in gltf.meshes() {
-            let indices_offset = dst.indices.len();
-            let positions_offset = dst.positions.len();
-            let normals_offset = dst.normals.len();
-            let uvs_offset = dst.uvs.len();
+            primitive_meshes.push(dst.meshes.len());
+            for primitive in mesh.primitives() {
+                let name = || {
+                    format!(
+                        "Primitive {} of mesh {} in {}",
+                        primitive.index(),
+                        mesh.index(),
+                        path.display()
+                    )
+                };
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    bail!("{} uses unsupported mode {:?}", name(), primitive.mode());
+                }
 
-            let primitive = mesh.primitives().next().unwrap();
-            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
 
-            for pos in reader.read_positions().unwrap() {
-                dst.positions.push(vec3(pos[0], pos[1], pos[2]));
-            }
-            for index in reader.read_indices().unwrap().into_u32() {
-                dst.indices.push(index);
-            }
-            for normal in reader.read_normals().unwrap() {
-                dst.normals.push(vec3(normal[0], normal[1], normal[2]));
-            }
-            for uv in reader.read_tex_coords(0).unwrap().into_f32() {
-                dst.uvs.push(vec2(uv[0], uv[1]));
-            }
+                let mut positions = reader
+                    .read_positions()
+                    .ok_or_else(|| anyhow!("{} has no positions", name()))?
+                    .map(Vec3::from)
+                    .collect::<Vec<_>>();
+                let mut indices = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+                if indices.len() % 3 != 0 {
+                    bail!(
+                        "{} has {} indices, which is not a triangle list",
+                        name(),
+                        indices.len()
+                    );
+                }
+                let vertex_count = positions.len();
+                ensure!(
+                    indices.iter().all(|&i| (i as usize) < vertex_count),
+                    "{} has out of range vertex indices",
+                    name()
+                );
+                // Every attribute needs a value per vertex.
+                let check_count = |attribute: &str, count: usize| {
+                    ensure!(
+                        count == vertex_count,
+                        "{} has {} {} values for {} vertices",
+                        name(),
+                        count,
+                        attribute,
+                        vertex_count
+                    );
+                    Ok(())
+                };
+                let mut uvs = match reader.read_tex_coords(0) {
+                    Some(uvs) => uvs.into_f32().map(Vec2::from).collect::<Vec<_>>(),
+                    None => vec![Vec2::ZERO; vertex_count],
+                };
+                check_count("TEXCOORD_0", uvs.len())?;
+                let mut tangents = reader
+                    .read_tangents()
+                    .map(|tangents| tangents.map(Vec4::from).collect::<Vec<_>>());
+                if let Some(tangents) = tangents.as_ref() {
+                    check_count("TANGENT", tangents.len())?;
+                }
+                let mut skin_vertices =
+                    reader
+                        .read_joints(0)
+                        .zip(reader.read_weights(0))
+                        .map(|(joints, weights)| {
+                            joints
+                                .into_u16()
+                                .zip(weights.into_f32())
+                                .map(|(joints, weights)| SkinVertex {
+                                    joints: Vec4::from(joints.map(|joint| joint as f32)),
+                                    weights: Vec4::from(weights),
+                                })
+                                .collect::<Vec<_>>()
+                        });
+                // Position, normal and tangent deltas of every morph target.
+                let mut targets = reader
+                    .read_morph_targets()
+                    .map(|(positions, normals, tangents)| {
+                        [
+                            positions.map(|p| p.map(Vec3::from).collect::<Vec<_>>()),
+                            normals.map(|n| n.map(Vec3::from).collect()),
+                            tangents.map(|t| t.map(Vec3::from).collect()),
+                        ]
+                        .map(|deltas| deltas.unwrap_or_else(|| vec![Vec3::ZERO; vertex_count]))
+                    })
+                    .collect::<Vec<_>>();
+                let normals = match reader.read_normals() {
+                    Some(normals) => {
+                        let normals = normals.map(Vec3::from).collect::<Vec<_>>();
+                        check_count("NORMAL", normals.len())?;
+                        normals
+                    }
+                    None => {
+                        // Flat normals need a vertex per triangle corner.
+                        positions = unweld(&indices, &positions);
+                        uvs = unweld(&indices, &uvs);
+                        skin_vertices = skin_vertices.map(|skin| unweld(&indices, &skin));
+                        for [positions, normals, tangents] in targets.iter_mut() {
+                            *positions = unweld(&indices, positions);
+                            // Flat normals and generated tangents are not morphed.
+                            *normals = vec![Vec3::ZERO; indices.len()];
+                            *tangents = vec![Vec3::ZERO; indices.len()];
+                        }
+                        indices = (0..indices.len() as u32).collect();
+                        tangents = None;
+                        flat_normals(&positions)
+                    }
+                };
+                let tangents = tangents
+                    .unwrap_or_else(|| generate_tangents(&indices, &positions, &normals, &uvs));
+
+                if skin_vertices.is_some() || !targets.is_empty() {
+                    let deformations = &mut dst.deformations;
+                    mesh_deformers.insert(dst.meshes.len(), deformations.deformers.len());
+                    // Joints are assigned by the node using the mesh.
+                    deformations.deformers.push(Deformer {
+                        mesh: dst.meshes.len() as u32,
+                        positions: dst.positions.len() as u32,
+                        normals: dst.normals.len() as u32,
+                        tangents: dst.tangents.len() as u32,
+                        vertex_count: positions.len() as u32,
+                        rest: deformations.rest_positions.len() as u32,
+                        joints: 0,
+                        joint_count: 0,
+                        morph: deformations.morph_positions.len() as u32,
+                        weights: deformations.morph_weights.len() as u32,
+                        target_count: targets.len() as u32,
+                    });
+                    deformations.rest_positions.extend(&positions);
+                    deformations.rest_normals.extend(&normals);
+                    deformations.rest_tangents.extend(&tangents);
+                    deformations.skin_vertices.extend(
+                        skin_vertices
+                            .unwrap_or_else(|| vec![SkinVertex::default(); positions.len()]),
+                    );
+                    let rest_weights = mesh.weights().unwrap_or(&[]);
+                    for (i, [positions, normals, tangents]) in targets.into_iter().enumerate() {
+                        deformations.morph_positions.extend(positions);
+                        deformations.morph_normals.extend(normals);
+                        deformations.morph_tangents.extend(tangents);
+                        deformations
+                            .morph_weights
+                            .push(rest_weights.get(i).copied().unwrap_or(0.));
+                    }
+                }
 
-            dst.meshes.push(Mesh {
-                indices: indices_offset as u32,
-                indices_count: dst.indices.len() as u32 - indices_offset as u32,
-                positions: positions_offset as u32,
-                normals: normals_offset as u32,
-                uvs: uvs_offset as u32,
-            })
+                dst.meshes.push(Mesh {
+                    indices: dst.indices.len() as u32,
+                    indices_count: indices.len() as u32,
+                    positions: dst.positions.len() as u32,
+                    normals: dst.normals.len() as u32,
+                    tangents: dst.tangents.len() as u32,
+                    uvs: dst.uvs.len() as u32,
+                });
+                dst.indices.extend(indices);
+                dst.positions.extend(positions);
+                dst.normals.extend(normals);
+                dst.tangents.extend(tangents);
+                dst.uvs.extend(uvs);
+            }
         }
 
         let material_offset = dst.materials.len();
         for material in gltf.materials() {
-            let emission = material.emissive_factor();
-            let mr_model = material.pbr_metallic_roughness();
-
-            let base_color = mr_model
-                .base_color_texture()
-                .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
-                .unwrap_or(Texture::constant(
-                    Vec4::from(mr_model.base_color_factor()).xyz(),
-                ));
-            let metallic_roughness = mr_model
-                .metallic_roughness_texture()
-                .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
-                .unwrap_or(Texture::constant(vec3(
-                    mr_model.metallic_factor(),
-                    mr_model.roughness_factor(),
-                    0.,
-                )));
-            // let emission = material
-            //     .emissive_texture()
-            //     .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
-            //     .unwrap_or(Texture::constant(Vec3::from(material.emissive_factor())));
-            let normal = material
-                .normal_texture()
-                .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
-                .unwrap_or(Texture::constant(vec3(0., 0., 1.)));
-            let transmission = material
-                .transmission()
-                .map(|t| {
-                    t.transmission_texture()
-                        .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
-                        .unwrap_or(Texture::constant(vec3(t.transmission_factor(), 0., 0.)))
-                })
-                .unwrap_or(Texture::constant(vec3(0., 0., 0.)));
-
-            dst.materials.push(Material {
-                base_color,
-                metallic_roughness,
-                normal,
-                transmission,
-            })
+            dst.materials.push(load_material(&material, texture_offset));
         }
+        // Primitives without material use the glTF default material, added on demand.
+        let mut default_material = None;
+
+        // Walk the default scene (or the first one) from its roots, files without scenes
+        // use every node that is not a child of another node as root.
+        let roots = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+            Some(scene) => scene.nodes().collect::<Vec<_>>(),
+            None => {
+                let children = gltf
+                    .nodes()
+                    .flat_map(|node| node.children().map(|child| child.index()))
+                    .collect::<std::collections::HashSet<_>>();
+                gltf.nodes()
+                    .filter(|node| !children.contains(&node.index()))
+                    .collect()
+            }
+        };
+        // Depth first in document order, so cameras keep their order in the file.
+        let mut stack = roots
+            .into_iter()
+            .rev()
+            .map(|node| (node, Mat4::IDENTITY, None))
+            .collect::<Vec<_>>();
+        // Visited nodes in the same order for animation, indexed by glTF node index.
+        let mut nodes = vec![];
+        let mut node_indices = HashMap::new();
+        // Deformers posed by a node, further nodes using the same mesh get their own copy.
+        let mut posed_deformers = HashSet::new();
 
         let instance_offset = dst.instances.len();
-        for node in gltf.nodes() {
+        while let Some((node, parent_to_world, parent)) = stack.pop() {
+            let to_world = parent_to_world * Mat4::from_cols_array_2d(&node.transform().matrix());
+            let index = nodes.len();
+            let children = node.children().collect::<Vec<_>>();
+            stack.extend(
+                children
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, to_world, Some(index))),
+            );
+
+            let (translation, rotation, scale) = node.transform().decomposed();
+            node_indices.insert(node.index(), index);
+            nodes.push(AnimationNode {
+                parent,
+                translation: Vec3::from(translation),
+                rotation: Quat::from_array(rotation),
+                scale: Vec3::from(scale),
+                instances: dst.instances.len()..dst.instances.len(),
+                camera: None,
+                deformers: vec![],
+                skin: node.skin().map(|skin| skin.index()),
+                weights: vec![],
+            });
+
             if let Some(camera) = node.camera() {
                 if let gltf::camera::Projection::Perspective(proj) = camera.projection() {
-                    let to_world = Mat4::from_cols_array_2d(&node.transform().matrix());
+                    nodes[index].camera = Some(dst.cameras.len());
                     dst.cameras.push(Camera::perspective(
                         to_world,
                         proj.yfov(),
@@ -123,34 +280,506 @@ impl Loader<Scene> for GltfLoader {
                     ));
                 }
             }
+            if let Some(light) = node.light() {
+                let position = to_world.transform_point3(Vec3::ZERO);
+                // Lights point along -z in their local frame.
+                let direction = to_world.transform_vector3(-Vec3::Z).normalize();
+                let color = Vec3::from(light.color());
+                // Candela for point and spot lights, lux for directional lights.
+                let intensity = color * light.intensity() / LUMENS_PER_WATT;
+
+                dst.emitters.push(match light.kind() {
+                    gltf::khr_lights_punctual::Kind::Point => Emitter::point(intensity, position),
+                    gltf::khr_lights_punctual::Kind::Spot {
+                        inner_cone_angle,
+                        outer_cone_angle,
+                    } => Emitter::spot(
+                        intensity,
+                        position,
+                        direction,
+                        inner_cone_angle,
+                        outer_cone_angle,
+                    ),
+                    gltf::khr_lights_punctual::Kind::Directional => {
+                        Emitter::directional(intensity, direction)
+                    }
+                });
+            }
             if let Some(mesh) = node.mesh() {
-                let matrix = node.transform().matrix();
-                let mut emitter = -1;
-                let material = mesh.primitives().next().unwrap().material();
+                nodes[index].weights = node
+                    .weights()
+                    .or(mesh.weights())
+                    .map(|weights| weights.to_vec())
+                    .unwrap_or_default();
+                // Scene mesh of every primitive, a copy for deformed meshes posed by another node.
+                let first = primitive_meshes[mesh.index()];
+                let mut scene_meshes =
+                    (first..first + mesh.primitives().count()).collect::<Vec<_>>();
+                for scene_mesh in scene_meshes.iter_mut() {
+                    let Some(&deformer) = mesh_deformers.get(scene_mesh) else {
+                        continue;
+                    };
+                    let deformer = if posed_deformers.insert(deformer) {
+                        deformer
+                    } else {
+                        let (copy, deformer) = copy_deformed_mesh(dst, deformer);
+                        *scene_mesh = copy;
+                        deformer
+                    };
+                    nodes[index].deformers.push(deformer);
+                    if let Some(skin) = node.skin() {
+                        let deformations = &mut dst.deformations;
+                        let joint_count = skin.joints().count();
+                        let deformer = &mut deformations.deformers[deformer];
+                        deformer.joints = deformations.joint_matrices.len() as u32;
+                        deformer.joint_count = joint_count as u32;
+                        deformations
+                            .joint_matrices
+                            .extend(std::iter::repeat_n(Mat4::IDENTITY, joint_count));
+                    }
+                }
+
+                // One instance per primitive, each with the material of its primitive.
+                for (primitive, &scene_mesh) in mesh.primitives().zip(scene_meshes.iter()) {
+                    let mut emitter = -1;
+                    let material = primitive.material();
+
+                    if material.emissive_factor() != [0., 0., 0.]
+                        && material.emissive_strength().unwrap_or(1.) > 0.
+                    {
+                        emitter = dst.emitters.len() as _;
+                        dst.emitters.push(Emitter::area(0));
+                    }
+
+                    let material_index = match material.index() {
+                        Some(index) => (material_offset + index) as u32,
+                        None => *default_material.get_or_insert_with(|| {
+                            dst.materials.push(load_material(&material, texture_offset));
+                            dst.materials.len() as u32 - 1
+                        }),
+                    };
+
+                    let instance = dst.instances.len();
+                    dst.instances.push(Instance {
+                        to_world,
+                        mesh: scene_mesh as u32,
+                        material: material_index,
+                        emitter,
+                    });
+                    if emitter >= 0 {
+                        dst.emitters[emitter as usize].instance = instance as u32;
+                    }
+                }
+            }
+            nodes[index].instances.end = dst.instances.len();
+        }
 
-                if material.emissive_texture().is_some()
-                    || material.emissive_factor() != [0., 0., 0.]
+        // One clip per animation, clips keep their index in the file.
+        let mut clips = vec![];
+        for animation in gltf.animations() {
+            let mut channels = vec![];
+            for channel in animation.channels() {
+                // Channels of nodes outside of the loaded scene have no effect.
+                let Some(&node) = node_indices.get(&channel.target().node().index()) else {
+                    continue;
+                };
+                let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+                let times = reader
+                    .read_inputs()
+                    .ok_or_else(|| anyhow!("Animation channel without keyframe times"))?
+                    .collect::<Vec<_>>();
+                let (target, values, components) = match reader
+                    .read_outputs()
+                    .ok_or_else(|| anyhow!("Animation channel without keyframe values"))?
                 {
-                    emitter = dst.emitters.len() as _;
-                    let emission = material
-                        .emissive_texture()
-                        .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
-                        .unwrap_or(Texture::constant(Vec3::from(material.emissive_factor())));
-                    dst.emitters.push(Emitter::area(emission, 0));
+                    ReadOutputs::Translations(translations) => (
+                        AnimationPath::Translation,
+                        translations.flatten().collect::<Vec<_>>(),
+                        3,
+                    ),
+                    ReadOutputs::Rotations(rotations) => (
+                        AnimationPath::Rotation,
+                        rotations.into_f32().flatten().collect(),
+                        4,
+                    ),
+                    ReadOutputs::Scales(scales) => {
+                        (AnimationPath::Scale, scales.flatten().collect(), 3)
+                    }
+                    ReadOutputs::MorphTargetWeights(weights) => {
+                        // Weights of nodes without morph targets have no effect.
+                        let components = nodes[node].weights.len();
+                        if components == 0 {
+                            continue;
+                        }
+                        (
+                            AnimationPath::Weights,
+                            weights.into_f32().collect(),
+                            components,
+                        )
+                    }
+                };
+                let interpolation = match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                };
+                let keyframe_values = match interpolation {
+                    Interpolation::CubicSpline => 3,
+                    _ => 1,
+                };
+                if times.is_empty() || values.len() != times.len() * keyframe_values * components {
+                    bail!(
+                        "Channel {} of animation {} in {} has {} keyframes but {} values",
+                        channel.index(),
+                        animation.index(),
+                        path.display(),
+                        times.len(),
+                        values.len() / components
+                    );
                 }
+                channels.push(Channel {
+                    node,
+                    path: target,
+                    interpolation,
+                    times,
+                    values,
+                    components,
+                });
+            }
+            clips.push(Clip::new(animation.name().map(str::to_owned), channels));
+        }
 
-                let instance = dst.instances.len();
-                dst.instances.push(Instance {
-                    to_world: Mat4::from_cols_array_2d(&matrix),
-                    mesh: mesh_offset as u32 + mesh.index() as u32,
-                    material: material_offset as u32 + material.index().unwrap() as u32,
-                    emitter,
+        // Skins are indexed like in the file, only skins of the loaded scene are read.
+        let mut skins = vec![];
+        for skin in gltf.skins() {
+            if !nodes.iter().any(|node| node.skin == Some(skin.index())) {
+                skins.push(Skin {
+                    joints: vec![],
+                    inverse_bind_matrices: vec![],
                 });
-                if emitter >= 0 {
-                    dst.emitters[emitter as usize].instance = instance as u32;
-                }
+                continue;
             }
+            let joints = skin
+                .joints()
+                .map(|joint| {
+                    node_indices.get(&joint.index()).copied().ok_or_else(|| {
+                        anyhow!(
+                            "Joint {} of skin {} in {} is not part of the scene",
+                            joint.index(),
+                            skin.index(),
+                            path.display()
+                        )
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+            let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+                Some(matrices) => matrices
+                    .map(|m| Mat4::from_cols_array_2d(&m))
+                    .collect::<Vec<_>>(),
+                None => vec![Mat4::IDENTITY; joints.len()],
+            };
+            ensure!(
+                inverse_bind_matrices.len() >= joints.len(),
+                "Skin {} in {} has fewer inverse bind matrices than joints",
+                skin.index(),
+                path.display()
+            );
+            skins.push(Skin {
+                joints,
+                inverse_bind_matrices,
+            });
         }
-        instance_offset
+
+        // Deformed meshes need an animation to be posed, even without clips.
+        if !clips.is_empty() || nodes.iter().any(|node| !node.deformers.is_empty()) {
+            dst.animations.push(Animation::new(nodes, clips, skins));
+        }
+
+        Ok(instance_offset)
+    }
+}
+
+///
+/// Copies a deformed mesh for another node using it, so both can be posed independently. The
+/// copy shares indices, texture coordinates, rest pose and morph targets with the original.
+/// Returns the mesh and deformer of the copy.
+///
+fn copy_deformed_mesh(dst: &mut Scene, deformer: usize) -> (usize, usize) {
+    let original = dst.deformations.deformers[deformer];
+    let mesh = &dst.meshes[original.mesh as usize];
+    let vertices = |offset: u32| offset as usize..(offset + original.vertex_count) as usize;
+    let copy = Mesh {
+        indices: mesh.indices,
+        indices_count: mesh.indices_count,
+        positions: dst.positions.len() as u32,
+        normals: dst.normals.len() as u32,
+        tangents: dst.tangents.len() as u32,
+        uvs: mesh.uvs,
+    };
+    dst.positions.extend_from_within(vertices(mesh.positions));
+    dst.normals.extend_from_within(vertices(mesh.normals));
+    dst.tangents.extend_from_within(vertices(mesh.tangents));
+
+    let deformations = &mut dst.deformations;
+    deformations.deformers.push(Deformer {
+        mesh: dst.meshes.len() as u32,
+        positions: copy.positions,
+        normals: copy.normals,
+        tangents: copy.tangents,
+        weights: deformations.morph_weights.len() as u32,
+        ..original
+    });
+    let weights = original.weights as usize..(original.weights + original.target_count) as usize;
+    deformations.morph_weights.extend_from_within(weights);
+    dst.meshes.push(copy);
+    (dst.meshes.len() - 1, deformations.deformers.len() - 1)
+}
+
+///
+/// Converts a glTF material, texture indices are offset by `texture_offset`.
+///
+fn load_material(material: &gltf::Material, texture_offset: usize) -> Material {
+    let mr_model = material.pbr_metallic_roughness();
+
+    let base_color = mr_model
+        .base_color_texture()
+        .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
+        .unwrap_or(Texture::constant(
+            Vec4::from(mr_model.base_color_factor()).xyz(),
+        ));
+    let metallic_roughness = mr_model
+        .metallic_roughness_texture()
+        .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
+        .unwrap_or(Texture::constant(vec3(
+            0.,
+            mr_model.roughness_factor(),
+            mr_model.metallic_factor(),
+        )));
+    let emission = material
+        .emissive_texture()
+        .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
+        .unwrap_or(Texture::constant(Vec3::ONE));
+    let emission_factor =
+        Vec3::from(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.);
+    let normal = material
+        .normal_texture()
+        .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
+        .unwrap_or(Texture::constant(vec3(0., 0., 1.)));
+    let normal_scale = material.normal_texture().map(|t| t.scale()).unwrap_or(1.);
+    let transmission = material
+        .transmission()
+        .map(|t| {
+            t.transmission_texture()
+                .map(|t| Texture::image(texture_offset as u32 + t.texture().index() as u32))
+                .unwrap_or(Texture::constant(vec3(t.transmission_factor(), 0., 0.)))
+        })
+        .unwrap_or(Texture::constant(vec3(0., 0., 0.)));
+    let ior = material.ior().unwrap_or(1.5);
+
+    Material {
+        base_color,
+        metallic_roughness,
+        normal,
+        transmission,
+        ior,
+        normal_scale,
+        emission,
+        emission_factor,
+    }
+}
+
+///
+/// Wraps the pixels decoded by `gltf::import` in an image of the matching format.
+///
+fn to_dynamic_image(data: &gltf::image::Data) -> Result<image::DynamicImage> {
+    use gltf::image::Format;
+    use image::{DynamicImage, ImageBuffer};
+
+    let (width, height) = (data.width, data.height);
+    let bytes = || data.pixels.clone();
+    let wide = || bytemuck::pod_collect_to_vec::<u8, u16>(&data.pixels);
+    let float = || bytemuck::pod_collect_to_vec::<u8, f32>(&data.pixels);
+    let img = match data.format {
+        Format::R8 => ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageLumaA8)
+        }
+        Format::R8G8B8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageRgb8)
+        }
+        Format::R8G8B8A8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageRgba8)
+        }
+        Format::R16 => ImageBuffer::from_raw(width, height, wide()).map(DynamicImage::ImageLuma16),
+        Format::R16G16 => {
+            ImageBuffer::from_raw(width, height, wide()).map(DynamicImage::ImageLumaA16)
+        }
+        Format::R16G16B16 => {
+            ImageBuffer::from_raw(width, height, wide()).map(DynamicImage::ImageRgb16)
+        }
+        Format::R16G16B16A16 => {
+            ImageBuffer::from_raw(width, height, wide()).map(DynamicImage::ImageRgba16)
+        }
+        Format::R32G32B32FLOAT => {
+            ImageBuffer::from_raw(width, height, float()).map(DynamicImage::ImageRgb32F)
+        }
+        Format::R32G32B32A32FLOAT => {
+            ImageBuffer::from_raw(width, height, float()).map(DynamicImage::ImageRgba32F)
+        }
+    };
+    img.ok_or_else(|| {
+        anyhow!(
+            "Image data does not match {width}x{height} {:?}",
+            data.format
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Loads one of the synthetic files in `assets/tests`, all of them contain a unit quad in the
+    /// xy plane.
+    ///
+    fn load(name: &str) -> Scene {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets/tests")
+            .join(name);
+        let mut scene = Scene::default();
+        GltfLoader::default().append(&path, &mut scene).unwrap();
+        scene
+    }
+
+    #[test]
+    fn missing_normals_are_flat() {
+        let scene = load("no-normals.gltf");
+        // One vertex per triangle corner.
+        assert_eq!(scene.indices, (0..6).collect::<Vec<_>>());
+        assert_eq!(scene.positions.len(), 6);
+        assert_eq!(scene.tangents.len(), 6);
+        assert!(scene.normals.iter().all(|n| n.abs_diff_eq(Vec3::Z, 1e-6)));
+        // Texture coordinates follow their vertices.
+        assert_eq!(scene.positions[4], vec3(1., 1., 0.));
+        assert_eq!(scene.uvs[4], vec2(1., 0.));
+    }
+
+    #[test]
+    fn missing_uvs_are_zero() {
+        let scene = load("no-uvs.gltf");
+        assert_eq!(scene.positions.len(), 4);
+        assert_eq!(scene.uvs, vec![Vec2::ZERO; 4]);
+        assert_eq!(scene.tangents.len(), 4);
+    }
+
+    #[test]
+    fn non_indexed_primitives_get_sequential_indices() {
+        let scene = load("non-indexed.gltf");
+        assert_eq!(scene.indices, (0..6).collect::<Vec<_>>());
+        assert_eq!(scene.meshes[0].indices_count, 6);
+    }
+
+    #[test]
+    fn missing_material_uses_one_default_material() {
+        let scene = load("no-material.gltf");
+        assert_eq!(scene.instances.len(), 2);
+        assert_eq!(scene.materials.len(), 1);
+        assert!(scene.instances.iter().all(|i| i.material == 0));
+
+        let material = &scene.materials[0];
+        assert_eq!(material.base_color.ty, Texture::TY_CONSTANT);
+        assert_eq!(material.base_color.val, Vec3::ONE);
+        assert_eq!(material.metallic_roughness.val, vec3(0., 1., 1.));
+        assert_eq!(material.emission_factor, Vec3::ZERO);
+    }
+
+    #[test]
+    fn out_of_range_indices_are_an_error() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/tests/bad-index.gltf");
+        let err = GltfLoader::default()
+            .append(path, &mut Scene::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{err}");
+    }
+
+    #[test]
+    fn attribute_counts_have_to_match_positions() {
+        for (name, attribute) in [
+            ("bad-uv-count.gltf", "TEXCOORD_0"),
+            ("bad-normal-count.gltf", "NORMAL"),
+        ] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("assets/tests")
+                .join(name);
+            let err = GltfLoader::default()
+                .append(path, &mut Scene::default())
+                .unwrap_err();
+            assert!(err.to_string().contains(attribute), "{err}");
+        }
+    }
+
+    #[test]
+    fn animations_are_separate_clips() {
+        let mut scene = load("two-clips.gltf");
+        assert_eq!(scene.animations.len(), 1);
+        let clips = &scene.animations[0].clips;
+        assert_eq!(clips.len(), 2);
+        assert_eq!(clips[0].name.as_deref(), Some("slide"));
+        assert_eq!(clips[0].duration, 1.);
+        assert_eq!(clips[1].name.as_deref(), Some("lift"));
+        assert_eq!(clips[1].duration, 2.);
+
+        let translation = |scene: &Scene| scene.instances[0].to_world.w_axis.xyz();
+        assert!(scene.animate(0, 0.5));
+        assert!(translation(&scene).abs_diff_eq(vec3(0.5, 0., 0.), 1e-6));
+        scene.animate(1, 0.5);
+        assert!(translation(&scene).abs_diff_eq(vec3(0., 0.5, 0.), 1e-6));
+        // Clips loop after their own duration.
+        scene.animate(0, 1.25);
+        assert!(translation(&scene).abs_diff_eq(vec3(0.25, 0., 0.), 1e-6));
+        // Files without the clip are posed at rest.
+        scene.animate(2, 0.5);
+        assert!(translation(&scene).abs_diff_eq(Vec3::ZERO, 1e-6));
+    }
+
+    #[test]
+    fn nodes_sharing_a_skinned_mesh_are_posed_separately() {
+        let mut scene = load("shared-skin.gltf");
+        assert_eq!(scene.meshes.len(), 2);
+        assert_eq!(scene.deformations.deformers.len(), 2);
+        assert_eq!(scene.instances[0].mesh, 0);
+        assert_eq!(scene.instances[1].mesh, 1);
+        // The copy only has its own vertex buffers.
+        let (mesh, copy) = (&scene.meshes[0], &scene.meshes[1]);
+        assert_eq!(copy.indices, mesh.indices);
+        assert_eq!(copy.uvs, mesh.uvs);
+        assert_ne!(copy.positions, mesh.positions);
+        assert_eq!(scene.positions[copy.positions as usize], scene.positions[0]);
+
+        scene.animate(0, 0.);
+        let joint = |deformer: usize| {
+            let joints = scene.deformations.deformers[deformer].joints;
+            scene.deformations.joint_matrices[joints as usize]
+        };
+        assert_eq!(joint(0), Mat4::from_translation(vec3(1., 0., 0.)));
+        assert_eq!(joint(1), Mat4::from_translation(vec3(1., 0., -5.)));
+    }
+
+    #[test]
+    fn glb_with_embedded_image() {
+        let scene = load("embedded.glb");
+        assert_eq!(scene.positions.len(), 4);
+        assert_eq!(scene.textures.len(), 1);
+
+        let material = &scene.materials[0];
+        assert_eq!(material.base_color.ty, Texture::TY_IMAGE);
+        assert_eq!(material.base_color.texture, 0);
+
+        let img = scene.textures[0].as_rgba8().unwrap();
+        assert_eq!(img.dimensions(), (2, 2));
+        assert_eq!(img.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(img.get_pixel(1, 1).0, [255, 255, 255, 255]);
     }
 }
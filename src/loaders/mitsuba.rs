@@ -0,0 +1,885 @@
+//! Loader for a subset of the Mitsuba 3 scene format, to render its reference scenes.
+//! Supported are obj, ply and rectangle shapes, the diffuse, (rough) conductor, (rough)
+//! dielectric and principled BSDFs, area, point, spot, directional, constant and envmap emitters
+//! and the perspective sensor with its film size and sample count.
+use crate::common::*;
+use crate::scene::{Film, Scene};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use glam::*;
+use roxmltree::Node;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+
+use super::envmap::append_env;
+use super::obj::obj_uvs;
+use super::ply::load_ply;
+use super::{append_mesh, Loader};
+
+#[derive(Default)]
+pub struct MitsubaLoader {}
+
+impl Loader<Scene> for MitsubaLoader {
+    fn append(&self, path: impl AsRef<Path>, dst: &mut Scene) -> Result<usize> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let doc = roxmltree::Document::parse(&text)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        let root = doc.root_element();
+        ensure!(
+            root.has_tag_name("scene"),
+            "{} is not a Mitsuba scene",
+            path.display()
+        );
+
+        let mut parser = Parser {
+            dir: path.parent().unwrap_or(Path::new("")).to_owned(),
+            ..Default::default()
+        };
+        let instance_offset = dst.instances.len();
+        for node in root.children().filter(Node::is_element) {
+            parser
+                .scene_element(node, dst)
+                .with_context(|| format!("In {} at {:?}", path.display(), node_position(node)))?;
+        }
+        Ok(instance_offset)
+    }
+}
+
+fn node_position(node: Node) -> roxmltree::TextPos {
+    node.document().text_pos_at(node.range().start)
+}
+
+///
+/// Mitsuba BSDFs approximated with the metallic roughness model.
+///
+#[derive(Debug, Clone, Copy)]
+struct Bsdf {
+    base_color: Texture,
+    roughness: f32,
+    metallic: f32,
+    transmission: f32,
+    ior: f32,
+}
+
+impl Default for Bsdf {
+    fn default() -> Self {
+        Self {
+            base_color: Texture::constant(Vec3::splat(0.5)),
+            roughness: 1.,
+            metallic: 0.,
+            transmission: 0.,
+            ior: 1.5,
+        }
+    }
+}
+
+impl Bsdf {
+    fn material(&self, radiance: Vec3) -> Material {
+        Material {
+            base_color: self.base_color,
+            metallic_roughness: Texture::constant(vec3(0., self.roughness, self.metallic)),
+            normal: Texture::constant(vec3(0., 0., 1.)),
+            transmission: Texture::constant(vec3(self.transmission, 0., 0.)),
+            ior: self.ior,
+            emission_factor: radiance,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Parser {
+    dir: PathBuf,
+    /// Parameters declared with `<default>`, referenced as `$name`.
+    defaults: HashMap<String, String>,
+    bsdfs: HashMap<String, Bsdf>,
+    /// Texture index of every loaded bitmap.
+    textures: HashMap<PathBuf, u32>,
+}
+
+impl Parser {
+    fn scene_element(&mut self, node: Node, dst: &mut Scene) -> Result<()> {
+        match node.tag_name().name() {
+            "default" => {
+                let name = self.attr(node, "name")?;
+                let value = self.attr(node, "value")?;
+                self.defaults.insert(name, value);
+            }
+            "bsdf" => {
+                let id = self.attr(node, "id")?;
+                let bsdf = self.bsdf(node, dst)?;
+                self.bsdfs.insert(id, bsdf);
+            }
+            "shape" => self.shape(node, dst)?,
+            "emitter" => self.emitter(node, dst)?,
+            "sensor" => self.sensor(node, dst)?,
+            "include" => bail!("<include> is not supported"),
+            // Integrators, samplers and other plugins have no equivalent.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    ///
+    /// Attribute with `$name` references replaced by their defaults.
+    ///
+    fn attr(&self, node: Node, name: &str) -> Result<String> {
+        let value = node
+            .attribute(name)
+            .ok_or_else(|| anyhow!("<{}> has no {name}", node.tag_name().name()))?;
+        Ok(match value.strip_prefix('$') {
+            Some(param) => self
+                .defaults
+                .get(param)
+                .cloned()
+                .ok_or_else(|| anyhow!("Undefined parameter ${param}"))?,
+            None => value.to_owned(),
+        })
+    }
+    fn ty(&self, node: Node) -> Result<String> {
+        self.attr(node, "type")
+    }
+
+    ///
+    /// Child element with the given name attribute.
+    ///
+    fn property<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+        node.children()
+            .find(|child| child.is_element() && child.attribute("name") == Some(name))
+    }
+    fn float(&self, node: Node, name: &str, default: f32) -> Result<f32> {
+        match Self::property(node, name) {
+            Some(prop) if prop.has_tag_name("float") || prop.has_tag_name("integer") => {
+                Ok(self.attr(prop, "value")?.trim().parse()?)
+            }
+            Some(prop) => bail!("{name} must be a float, found <{}>", prop.tag_name().name()),
+            None => Ok(default),
+        }
+    }
+    fn integer(&self, node: Node, name: &str, default: u32) -> Result<u32> {
+        match Self::property(node, name) {
+            Some(prop) => Ok(self.attr(prop, "value")?.trim().parse()?),
+            None => Ok(default),
+        }
+    }
+    fn string(&self, node: Node, name: &str) -> Result<Option<String>> {
+        Self::property(node, name)
+            .map(|prop| self.attr(prop, "value"))
+            .transpose()
+    }
+    fn boolean(&self, node: Node, name: &str) -> Result<bool> {
+        Ok(self.string(node, name)?.is_some_and(|b| b == "true"))
+    }
+    ///
+    /// `rgb`, `spectrum` or `float` property, spectra have to be constant.
+    ///
+    fn color(&self, node: Node, name: &str) -> Result<Option<Vec3>> {
+        let Some(prop) = Self::property(node, name) else {
+            return Ok(None);
+        };
+        match prop.tag_name().name() {
+            "rgb" | "spectrum" | "float" => {
+                let value = self.attr(prop, "value")?;
+                let v = parse_floats(&value)
+                    .with_context(|| format!("{name} must be an RGB value or constant"))?;
+                match v[..] {
+                    [x] => Ok(Some(Vec3::splat(x))),
+                    [x, y, z] => Ok(Some(vec3(x, y, z))),
+                    _ => bail!("{name} must have 1 or 3 components"),
+                }
+            }
+            tag => bail!("{name} must be a color, found <{tag}>"),
+        }
+    }
+    ///
+    /// `point` or `vector` given either as `x`, `y`, `z` attributes or as `value`.
+    ///
+    fn point(&self, node: Node, default: f32) -> Result<Vec3> {
+        if node.has_attribute("value") {
+            return match parse_floats(&self.attr(node, "value")?)?[..] {
+                [x] => Ok(Vec3::splat(x)),
+                [x, y, z] => Ok(vec3(x, y, z)),
+                _ => bail!("<{}> must have 1 or 3 components", node.tag_name().name()),
+            };
+        }
+        let component = |c| -> Result<f32> {
+            if node.has_attribute(c) {
+                self.float_attr(node, c)
+            } else {
+                Ok(default)
+            }
+        };
+        Ok(vec3(component("x")?, component("y")?, component("z")?))
+    }
+    ///
+    /// Composes the operations of the `to_world` transform, later operations are applied last.
+    ///
+    fn transform(&self, node: Node) -> Result<Mat4> {
+        let Some(transform) = Self::property(node, "to_world") else {
+            return Ok(Mat4::IDENTITY);
+        };
+        let mut to_world = Mat4::IDENTITY;
+        for op in transform.children().filter(Node::is_element) {
+            let m = match op.tag_name().name() {
+                "translate" => Mat4::from_translation(self.point(op, 0.)?),
+                "scale" => Mat4::from_scale(self.point(op, 1.)?),
+                "rotate" => {
+                    let angle = self.float_attr(op, "angle")?.to_radians();
+                    Mat4::from_axis_angle(self.point(op, 0.)?.normalize(), angle)
+                }
+                "matrix" => {
+                    let v = parse_floats(&self.attr(op, "value")?)?;
+                    match v.len() {
+                        16 => Mat4::from_cols_slice(&v).transpose(),
+                        9 => Mat4::from_mat3(Mat3::from_cols_slice(&v).transpose()),
+                        n => bail!("Matrix with {n} entries"),
+                    }
+                }
+                "lookat" => {
+                    let origin = self.vec3_attr(op, "origin")?;
+                    let target = self.vec3_attr(op, "target")?;
+                    let up = if op.has_attribute("up") {
+                        self.vec3_attr(op, "up")?
+                    } else {
+                        Vec3::Y
+                    };
+                    let dir = (target - origin).normalize();
+                    let left = up.cross(dir).normalize();
+                    let up = dir.cross(left);
+                    Mat4::from_cols(
+                        left.extend(0.),
+                        up.extend(0.),
+                        dir.extend(0.),
+                        origin.extend(1.),
+                    )
+                }
+                tag => bail!("Unsupported transform <{tag}>"),
+            };
+            to_world = m * to_world;
+        }
+        Ok(to_world)
+    }
+    fn float_attr(&self, node: Node, name: &str) -> Result<f32> {
+        Ok(self.attr(node, name)?.trim().parse()?)
+    }
+    fn vec3_attr(&self, node: Node, name: &str) -> Result<Vec3> {
+        match parse_floats(&self.attr(node, name)?)?[..] {
+            [x, y, z] => Ok(vec3(x, y, z)),
+            _ => bail!("{name} must have 3 components"),
+        }
+    }
+
+    ///
+    /// Constant or bitmap texture.
+    ///
+    fn texture(
+        &mut self,
+        node: Node,
+        name: &str,
+        default: Vec3,
+        dst: &mut Scene,
+    ) -> Result<Texture> {
+        let Some(prop) = Self::property(node, name) else {
+            return Ok(Texture::constant(default));
+        };
+        if !prop.has_tag_name("texture") {
+            return Ok(Texture::constant(
+                self.color(node, name)?.unwrap_or(default),
+            ));
+        }
+        let ty = self.ty(prop)?;
+        ensure!(ty == "bitmap", "Unsupported texture type {ty}");
+
+        let filename = self
+            .string(prop, "filename")?
+            .ok_or_else(|| anyhow!("Bitmap without filename"))?;
+        let path = self.dir.join(filename);
+        if let Some(&index) = self.textures.get(&path) {
+            return Ok(Texture::image(index));
+        }
+        let img =
+            image::open(&path).with_context(|| format!("Failed to load {}", path.display()))?;
+        let index = dst.textures.len() as u32;
+        dst.textures
+            .push(image::DynamicImage::ImageRgba8(img.into_rgba8()));
+        self.textures.insert(path, index);
+        Ok(Texture::image(index))
+    }
+
+    fn bsdf(&mut self, node: Node, dst: &mut Scene) -> Result<Bsdf> {
+        let ty = self.ty(node)?;
+        let alpha = |parser: &Self, default| -> Result<f32> {
+            Ok(parser.float(node, "alpha", default)?.max(0.).sqrt())
+        };
+        Ok(match ty.as_str() {
+            // Both sides are shaded the same anyway.
+            "twosided" => {
+                let inner = node
+                    .children()
+                    .find(|child| child.has_tag_name("bsdf") || child.has_tag_name("ref"))
+                    .ok_or_else(|| anyhow!("twosided without nested BSDF"))?;
+                self.nested_bsdf(inner, dst)?
+            }
+            "diffuse" => Bsdf {
+                base_color: self.texture(node, "reflectance", Vec3::splat(0.5), dst)?,
+                ..Default::default()
+            },
+            "conductor" | "roughconductor" => {
+                let eta = self.color(node, "eta")?;
+                let k = self.color(node, "k")?;
+                // Normal incidence reflectance of the complex index of refraction.
+                let f0 = match (eta, k) {
+                    (Some(eta), Some(k)) => {
+                        ((eta - 1.) * (eta - 1.) + k * k) / ((eta + 1.) * (eta + 1.) + k * k)
+                    }
+                    _ => Vec3::ONE,
+                };
+                let specular = self
+                    .color(node, "specular_reflectance")?
+                    .unwrap_or(Vec3::ONE);
+                Bsdf {
+                    base_color: Texture::constant(f0 * specular),
+                    roughness: alpha(self, if ty == "conductor" { 0. } else { 0.1 })?,
+                    metallic: 1.,
+                    ..Default::default()
+                }
+            }
+            "dielectric" | "roughdielectric" | "thindielectric" => Bsdf {
+                base_color: Texture::constant(
+                    self.color(node, "specular_transmittance")?
+                        .unwrap_or(Vec3::ONE),
+                ),
+                roughness: alpha(self, if ty == "roughdielectric" { 0.1 } else { 0. })?,
+                transmission: 1.,
+                ior: self.ior(node, "int_ior", 1.5046)? / self.ior(node, "ext_ior", 1.000277)?,
+                ..Default::default()
+            },
+            "principled" => Bsdf {
+                base_color: self.texture(node, "base_color", Vec3::splat(0.5), dst)?,
+                roughness: self.float(node, "roughness", 0.5)?,
+                metallic: self.float(node, "metallic", 0.)?,
+                transmission: self.float(node, "spec_trans", 0.)?,
+                ior: self.float(node, "eta", 1.5)?,
+            },
+            _ => bail!("Unsupported BSDF type {ty}"),
+        })
+    }
+    ///
+    /// Inline `<bsdf>` or `<ref>` to a BSDF declared before.
+    ///
+    fn nested_bsdf(&mut self, node: Node, dst: &mut Scene) -> Result<Bsdf> {
+        if node.has_tag_name("ref") {
+            let id = self.attr(node, "id")?;
+            return self
+                .bsdfs
+                .get(&id)
+                .copied()
+                .ok_or_else(|| anyhow!("Unknown BSDF {id}"));
+        }
+        self.bsdf(node, dst)
+    }
+    ///
+    /// Index of refraction as number or as one of the common named materials.
+    ///
+    fn ior(&self, node: Node, name: &str, default: f32) -> Result<f32> {
+        let Some(value) = self.string(node, name)? else {
+            return Ok(default);
+        };
+        Ok(match value.as_str() {
+            "vacuum" => 1.,
+            "air" => 1.000277,
+            "water" => 1.333,
+            "acrylic glass" | "polypropylene" => 1.49,
+            "bk7" => 1.5046,
+            "diamond" => 2.419,
+            _ => value
+                .parse()
+                .map_err(|_| anyhow!("Unknown index of refraction {value}"))?,
+        })
+    }
+
+    fn shape(&mut self, node: Node, dst: &mut Scene) -> Result<()> {
+        let ty = self.ty(node)?;
+        let to_world = self.transform(node)?;
+        let face_normals = self.boolean(node, "face_normals")?;
+
+        let mut meshes = vec![];
+        match ty.as_str() {
+            "obj" => {
+                let path = self.mesh_path(node)?;
+                let (models, _) = tobj::load_obj(
+                    &path,
+                    &tobj::LoadOptions {
+                        single_index: true,
+                        triangulate: true,
+                        ..Default::default()
+                    },
+                )
+                .with_context(|| format!("Failed to load {}", path.display()))?;
+                for model in models.iter().filter(|model| !model.mesh.indices.is_empty()) {
+                    let mesh = &model.mesh;
+                    let positions = mesh
+                        .positions
+                        .chunks_exact(3)
+                        .map(Vec3::from_slice)
+                        .collect::<Vec<_>>();
+                    let normals = (!mesh.normals.is_empty() && !face_normals)
+                        .then(|| mesh.normals.chunks_exact(3).map(Vec3::from_slice).collect());
+                    let uvs = obj_uvs(mesh, positions.len());
+                    meshes.push(append_mesh(
+                        dst,
+                        mesh.indices.clone(),
+                        positions,
+                        normals,
+                        uvs,
+                    )?);
+                }
+            }
+            "ply" => {
+                let path = self.mesh_path(node)?;
+                let ply = load_ply(&path)?;
+                let normals = ply.normals.filter(|_| !face_normals);
+                let uvs = ply
+                    .uvs
+                    .unwrap_or_else(|| vec![Vec2::ZERO; ply.positions.len()]);
+                meshes.push(append_mesh(dst, ply.indices, ply.positions, normals, uvs)?);
+            }
+            "rectangle" => meshes.push(append_mesh(
+                dst,
+                vec![0, 1, 2, 0, 2, 3],
+                vec![
+                    vec3(-1., -1., 0.),
+                    vec3(1., -1., 0.),
+                    vec3(1., 1., 0.),
+                    vec3(-1., 1., 0.),
+                ],
+                Some(vec![Vec3::Z; 4]),
+                vec![vec2(0., 0.), vec2(1., 0.), vec2(1., 1.), vec2(0., 1.)],
+            )?),
+            _ => bail!("Unsupported shape type {ty}"),
+        }
+
+        let mut bsdf = Bsdf::default();
+        let mut radiance = Vec3::ZERO;
+        for child in node.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "bsdf" | "ref" => bsdf = self.nested_bsdf(child, dst)?,
+                "emitter" => {
+                    let ty = self.ty(child)?;
+                    ensure!(ty == "area", "Unsupported shape emitter type {ty}");
+                    radiance = self.color(child, "radiance")?.unwrap_or(Vec3::ONE);
+                }
+                _ => {}
+            }
+        }
+
+        let material = dst.materials.len() as u32;
+        dst.materials.push(bsdf.material(radiance));
+        for mesh in meshes {
+            let instance = dst.instances.len();
+            let mut emitter = -1;
+            if radiance != Vec3::ZERO {
+                emitter = dst.emitters.len() as _;
+                dst.emitters.push(Emitter::area(instance as u32));
+            }
+            dst.instances.push(Instance {
+                to_world,
+                mesh: mesh as u32,
+                material,
+                emitter,
+            });
+        }
+        Ok(())
+    }
+    fn mesh_path(&self, node: Node) -> Result<PathBuf> {
+        let filename = self
+            .string(node, "filename")?
+            .ok_or_else(|| anyhow!("Shape without filename"))?;
+        Ok(self.dir.join(filename))
+    }
+
+    fn emitter(&mut self, node: Node, dst: &mut Scene) -> Result<()> {
+        let ty = self.ty(node)?;
+        let to_world = self.transform(node)?;
+        match ty.as_str() {
+            "envmap" => {
+                let filename = self
+                    .string(node, "filename")?
+                    .ok_or_else(|| anyhow!("envmap without filename"))?;
+                let path = self.dir.join(filename);
+                let mut img = image::open(&path)
+                    .with_context(|| format!("Failed to load {}", path.display()))?
+                    .into_rgba32f();
+                let scale = self.float(node, "scale", 1.)?;
+                for p in img.pixels_mut() {
+                    for c in p.0[..3].iter_mut() {
+                        *c *= scale;
+                    }
+                }
+                // The environment has no transform of its own, rotate the image instead. Mitsuba
+                // maps u = 0 to -z where we map u = 0.5, which is a half turn around +y.
+                let img = rotate_env(&img, to_world * Mat4::from_rotation_y(PI));
+                append_env(img, dst);
+            }
+            "constant" => {
+                let radiance = self.color(node, "radiance")?.unwrap_or(Vec3::ONE);
+                let img = image::Rgba32FImage::from_pixel(
+                    2,
+                    1,
+                    image::Rgba(radiance.extend(1.).to_array()),
+                );
+                append_env(img, dst);
+            }
+            "point" => {
+                let position = match Self::property(node, "position") {
+                    Some(position) => self.point(position, 0.)?,
+                    None => to_world.transform_point3(Vec3::ZERO),
+                };
+                let intensity = self.color(node, "intensity")?.unwrap_or(Vec3::ONE);
+                dst.emitters.push(Emitter::point(intensity, position));
+            }
+            "spot" => {
+                let intensity = self.color(node, "intensity")?.unwrap_or(Vec3::ONE);
+                let cutoff = self.float(node, "cutoff_angle", 20.)?;
+                let beam_width = self.float(node, "beam_width", cutoff * 3. / 4.)?;
+                dst.emitters.push(Emitter::spot(
+                    intensity,
+                    to_world.transform_point3(Vec3::ZERO),
+                    to_world.transform_vector3(Vec3::Z).normalize(),
+                    beam_width.to_radians(),
+                    cutoff.to_radians(),
+                ));
+            }
+            "directional" => {
+                let direction = match Self::property(node, "direction") {
+                    Some(direction) => self.point(direction, 0.)?,
+                    None => to_world.transform_vector3(Vec3::Z),
+                };
+                let irradiance = self.color(node, "irradiance")?.unwrap_or(Vec3::ONE);
+                dst.emitters
+                    .push(Emitter::directional(irradiance, direction.normalize()));
+            }
+            _ => bail!("Unsupported emitter type {ty}"),
+        }
+        Ok(())
+    }
+
+    fn sensor(&mut self, node: Node, dst: &mut Scene) -> Result<()> {
+        let ty = self.ty(node)?;
+        ensure!(ty == "perspective", "Unsupported sensor type {ty}");
+
+        let film = node.children().find(|child| child.has_tag_name("film"));
+        let width = film.map_or(Ok(768), |film| self.integer(film, "width", 768))?;
+        let height = film.map_or(Ok(576), |film| self.integer(film, "height", 576))?;
+        let sampler = node.children().find(|child| child.has_tag_name("sampler"));
+        let spp = sampler
+            .map(|sampler| self.integer(sampler, "sample_count", 4))
+            .transpose()?;
+        dst.film.get_or_insert(Film { width, height, spp });
+
+        // Horizontal field of view of the default 50mm focal length.
+        let fov = self.float(node, "fov", 39.5978)?.to_radians();
+        let aspect = width as f32 / height as f32;
+        let fov_axis = self.string(node, "fov_axis")?.unwrap_or("x".into());
+        let fov_y = match fov_axis.as_str() {
+            "y" => fov,
+            "x" => 2. * ((fov / 2.).tan() / aspect).atan(),
+            "diagonal" => 2. * ((fov / 2.).tan() / (1. + aspect * aspect).sqrt()).atan(),
+            "smaller" if aspect < 1. => 2. * ((fov / 2.).tan() / aspect).atan(),
+            "larger" if aspect > 1. => 2. * ((fov / 2.).tan() / aspect).atan(),
+            "smaller" | "larger" => fov,
+            _ => bail!("Unknown fov_axis {fov_axis}"),
+        };
+
+        // Mitsuba cameras look along +z with +x to the left, ours along -z with +x to the right.
+        let to_world = self.transform(node)? * Mat4::from_scale(vec3(-1., 1., -1.));
+        dst.cameras.push(Camera::perspective(
+            to_world,
+            fov_y,
+            aspect,
+            self.float(node, "near_clip", 0.01)?,
+            self.float(node, "far_clip", 10000.)?,
+        ));
+        Ok(())
+    }
+}
+
+///
+/// Parses comma and/or whitespace separated floats.
+///
+fn parse_floats(s: &str) -> Result<Vec<f32>> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            x.parse::<f32>()
+                .with_context(|| format!("Invalid number {x}"))
+        })
+        .collect()
+}
+
+///
+/// Direction of the equirectangular image coordinates `uv`, same mapping as
+/// `env_uv_to_direction` in emitter.glsl.
+///
+fn env_uv_to_direction(uv: Vec2) -> Vec3 {
+    let phi = (uv.x - 0.5) * 2. * PI;
+    let theta = uv.y * PI;
+    vec3(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+fn env_direction_to_uv(d: Vec3) -> Vec2 {
+    vec2(
+        d.x.atan2(-d.z) / (2. * PI) + 0.5,
+        d.y.clamp(-1., 1.).acos() / PI,
+    )
+}
+
+///
+/// Resamples an equirectangular image such that looking up a world space direction returns
+/// the value of the image in the local frame of `to_world`, with bilinear filtering.
+///
+fn rotate_env(img: &image::Rgba32FImage, to_world: Mat4) -> image::Rgba32FImage {
+    let (width, height) = img.dimensions();
+    let to_local = to_world.inverse();
+    let texel = |x: i64, y: i64| {
+        let x = x.rem_euclid(width as i64) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        Vec4::from(img.get_pixel(x, y).0)
+    };
+    image::Rgba32FImage::from_fn(width, height, |x, y| {
+        let uv = vec2(
+            (x as f32 + 0.5) / width as f32,
+            (y as f32 + 0.5) / height as f32,
+        );
+        let d = to_local
+            .transform_vector3(env_uv_to_direction(uv))
+            .normalize();
+        let p = env_direction_to_uv(d) * vec2(width as f32, height as f32) - 0.5;
+        let (x0, y0) = (p.x.floor(), p.y.floor());
+        let (tx, ty) = (p.x - x0, p.y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = texel(x0, y0).lerp(texel(x0 + 1, y0), tx);
+        let bottom = texel(x0, y0 + 1).lerp(texel(x0 + 1, y0 + 1), tx);
+        image::Rgba(top.lerp(bottom, ty).to_array())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Loads `xml` from a directory of its own, `setup` can add further files to it.
+    ///
+    fn load_with(test: &str, xml: &str, setup: impl FnOnce(&Path)) -> Result<Scene> {
+        let dir = std::env::temp_dir().join(format!("mitsuba-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("scene.xml"), xml).unwrap();
+        setup(&dir);
+        let mut scene = Scene::default();
+        let result = MitsubaLoader::default().append(dir.join("scene.xml"), &mut scene);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.map(|_| scene)
+    }
+    fn load(test: &str, xml: &str) -> Result<Scene> {
+        load_with(test, xml, |_| {})
+    }
+
+    ///
+    /// Scene with a rectangle using the given BSDF and `to_world` operations.
+    ///
+    fn rectangle(bsdf: &str, to_world: &str) -> String {
+        format!(
+            r#"<scene version="3.0.0">
+                <shape type="rectangle">
+                    <transform name="to_world">{to_world}</transform>
+                    {bsdf}
+                </shape>
+            </scene>"#
+        )
+    }
+
+    fn sensor(properties: &str) -> String {
+        format!(
+            r#"<scene version="3.0.0">
+                <default name="spp" value="16"/>
+                <default name="width" value="200"/>
+                <sensor type="perspective">
+                    {properties}
+                    <sampler type="independent">
+                        <integer name="sample_count" value="$spp"/>
+                    </sampler>
+                    <film type="hdrfilm">
+                        <integer name="width" value="$width"/>
+                        <integer name="height" value="100"/>
+                    </film>
+                </sensor>
+            </scene>"#
+        )
+    }
+
+    #[test]
+    fn later_transforms_are_applied_last() {
+        let scene = load(
+            "transform",
+            &rectangle("", r#"<translate x="1"/><rotate y="1" angle="90"/>"#),
+        )
+        .unwrap();
+        let origin = scene.instances[0].to_world.transform_point3(Vec3::ZERO);
+        assert!(origin.abs_diff_eq(vec3(0., 0., -1.), 1e-6), "{origin}");
+
+        // Matrices are given row by row.
+        let scene = load(
+            "matrix",
+            &rectangle("", r#"<matrix value="1 0 0 2 0 1 0 3 0 0 1 4 0 0 0 1"/>"#),
+        )
+        .unwrap();
+        assert_eq!(
+            scene.instances[0].to_world,
+            Mat4::from_translation(vec3(2., 3., 4.))
+        );
+    }
+
+    #[test]
+    fn lookat_sensor() {
+        let scene = load(
+            "lookat",
+            &sensor(
+                r#"<transform name="to_world">
+                    <lookat origin="0, 0, 5" target="0, 0, 0" up="0, 1, 0"/>
+                </transform>"#,
+            ),
+        )
+        .unwrap();
+        let to_world = scene.cameras[0].to_world;
+        assert!(to_world
+            .transform_point3(Vec3::ZERO)
+            .abs_diff_eq(vec3(0., 0., 5.), 1e-6));
+        // Our cameras look along -z with +x to the right and +y up.
+        assert!(to_world
+            .transform_vector3(-Vec3::Z)
+            .abs_diff_eq(-Vec3::Z, 1e-6));
+        assert!(to_world
+            .transform_vector3(Vec3::X)
+            .abs_diff_eq(Vec3::X, 1e-6));
+        assert!(to_world
+            .transform_vector3(Vec3::Y)
+            .abs_diff_eq(Vec3::Y, 1e-6));
+    }
+
+    #[test]
+    fn fov_axis_is_converted_to_vertical() {
+        // The film is twice as wide as high.
+        let half_x = (PI / 4.).tan();
+        for (axis, fov_y) in [
+            ("x", 2. * (half_x / 2.).atan()),
+            ("y", PI / 2.),
+            ("smaller", PI / 2.),
+            ("larger", 2. * (half_x / 2.).atan()),
+            ("diagonal", 2. * (half_x / 5f32.sqrt()).atan()),
+        ] {
+            let scene = load(
+                "fov",
+                &sensor(&format!(
+                    r#"<float name="fov" value="90"/><string name="fov_axis" value="{axis}"/>"#
+                )),
+            )
+            .unwrap();
+            assert!((scene.cameras[0].fov_y - fov_y).abs() < 1e-5, "{axis}");
+        }
+    }
+
+    #[test]
+    fn defaults_are_substituted() {
+        let scene = load("defaults", &sensor("")).unwrap();
+        let film = scene.film.unwrap();
+        assert_eq!((film.width, film.height, film.spp), (200, 100, Some(16)));
+
+        let err = load("undefined", &sensor("").replace("$spp", "$samples"))
+            .map(|_| ())
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("$samples"), "{err:#}");
+    }
+
+    #[test]
+    fn named_and_numeric_iors() {
+        let dielectric = |int_ior: &str| {
+            let bsdf = format!(
+                r#"<bsdf type="dielectric">
+                    {int_ior}
+                    <string name="ext_ior" value="air"/>
+                </bsdf>"#
+            );
+            load("ior", &rectangle(&bsdf, "")).map(|scene| scene.materials[0].ior)
+        };
+        let ior = dielectric(r#"<string name="int_ior" value="water"/>"#).unwrap();
+        assert!((ior - 1.333 / 1.000277).abs() < 1e-6);
+        let ior = dielectric(r#"<float name="int_ior" value="1.7"/>"#).unwrap();
+        assert!((ior - 1.7 / 1.000277).abs() < 1e-6);
+        assert!(dielectric(r#"<string name="int_ior" value="glass"/>"#).is_err());
+    }
+
+    #[test]
+    fn conductor_reflectance_at_normal_incidence() {
+        let bsdf = r#"<bsdf type="roughconductor">
+            <rgb name="eta" value="0.2, 0.5, 1"/>
+            <rgb name="k" value="3, 2, 1"/>
+            <float name="alpha" value="0.25"/>
+        </bsdf>"#;
+        let scene = load("conductor", &rectangle(bsdf, "")).unwrap();
+        let material = &scene.materials[0];
+        let f0 = |eta: f32, k: f32| ((eta - 1.).powi(2) + k * k) / ((eta + 1.).powi(2) + k * k);
+        assert!(material
+            .base_color
+            .val
+            .abs_diff_eq(vec3(f0(0.2, 3.), f0(0.5, 2.), f0(1., 1.)), 1e-6));
+        // Roughness is the square root of alpha.
+        assert_eq!(material.metallic_roughness.val, vec3(0., 0.5, 1.));
+    }
+
+    #[test]
+    fn envmap_matches_mitsuba_orientation() {
+        // Only the first column is lit, Mitsuba maps its center to a direction 22.5° from -z
+        // towards +x.
+        let envmap = |to_world: &str| {
+            let xml = format!(
+                r#"<scene version="3.0.0">
+                    <emitter type="envmap">
+                        <string name="filename" value="env.png"/>
+                        <transform name="to_world">{to_world}</transform>
+                    </emitter>
+                </scene>"#
+            );
+            let scene = load_with("envmap", &xml, |dir| {
+                image::RgbaImage::from_fn(8, 4, |x, _| {
+                    image::Rgba(if x == 0 { [255; 4] } else { [0, 0, 0, 255] })
+                })
+                .save(dir.join("env.png"))
+                .unwrap();
+            })
+            .unwrap();
+            let img = scene.textures[0].as_rgba32f().unwrap().clone();
+            (0..8).map(|x| img.get_pixel(x, 1)[0]).collect::<Vec<_>>()
+        };
+
+        // Our mapping has that direction at the center of the fifth column.
+        let columns = envmap("");
+        assert!((columns[4] - 1.).abs() < 1e-4, "{columns:?}");
+        assert!(columns[0] < 1e-4, "{columns:?}");
+        let direction = env_uv_to_direction(vec2(4.5 / 8., 0.5));
+        assert!(direction.x > 0. && direction.z < 0.);
+        assert!((direction.x.atan2(-direction.z) - PI / 8.).abs() < 1e-5);
+
+        // A quarter turn around +y moves it by two columns.
+        let columns = envmap(r#"<rotate y="1" angle="90"/>"#);
+        assert!((columns[2] - 1.).abs() < 1e-4, "{columns:?}");
+        assert!(columns[4] < 1e-4, "{columns:?}");
+    }
+}
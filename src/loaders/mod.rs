@@ -1,8 +1,108 @@
+mod envmap;
 mod gltf;
+mod mitsuba;
+mod obj;
+mod ply;
+mod tangents;
+pub use self::envmap::*;
 pub use self::gltf::*;
+pub use self::mitsuba::*;
+pub use self::obj::*;
 
-use std::path::Path;
+use crate::common::Mesh;
+use crate::scene::Scene;
+use anyhow::{ensure, Result};
+use glam::*;
+use std::path::{Path, PathBuf};
 
 pub trait Loader<T> {
-    fn append(&self, path: impl AsRef<Path>, dst: &mut T) -> usize;
+    fn append(&self, path: impl AsRef<Path>, dst: &mut T) -> Result<usize>;
+}
+
+///
+/// Loads all scene files into one scene.
+/// Wavefront OBJ files are loaded with the [`ObjLoader`], Mitsuba scenes (.xml) with the
+/// [`MitsubaLoader`], HDR images (.hdr, .exr) as the
+/// environment map and everything else as glTF.
+/// Fails on the first file that can not be loaded.
+///
+pub fn load_scene(paths: &[PathBuf]) -> Result<Scene> {
+    let mut scene = Scene::default();
+    for path in paths {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("hdr" | "exr") => EnvMapLoader::default().append(path, &mut scene)?,
+            Some("obj") => ObjLoader::default().append(path, &mut scene)?,
+            Some("xml") => MitsubaLoader::default().append(path, &mut scene)?,
+            _ => GltfLoader::default().append(path, &mut scene)?,
+        };
+    }
+    Ok(scene)
+}
+
+///
+/// Resolves indexed vertex data into one entry per index.
+///
+fn unweld<T: Copy>(indices: &[u32], data: &[T]) -> Vec<T> {
+    indices.iter().map(|&i| data[i as usize]).collect()
+}
+
+///
+/// Per vertex normals of an unindexed triangle list, every vertex gets its face normal.
+///
+fn flat_normals(positions: &[Vec3]) -> Vec<Vec3> {
+    positions
+        .chunks_exact(3)
+        .flat_map(|p| [(p[1] - p[0]).cross(p[2] - p[0]).normalize_or_zero(); 3])
+        .collect()
+}
+
+///
+/// Appends an indexed triangle mesh and returns its index, flat normals are generated if the
+/// mesh has none. Fails if an index or the number of normals or uvs does not match the
+/// positions.
+///
+fn append_mesh(
+    dst: &mut Scene,
+    mut indices: Vec<u32>,
+    mut positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    mut uvs: Vec<Vec2>,
+) -> Result<usize> {
+    ensure!(
+        indices.iter().all(|&i| (i as usize) < positions.len()),
+        "Mesh has out of range vertex indices"
+    );
+    ensure!(
+        normals
+            .as_ref()
+            .is_none_or(|normals| normals.len() == positions.len())
+            && uvs.len() == positions.len(),
+        "Mesh has {} positions but a different number of normals or uvs",
+        positions.len()
+    );
+    let normals = match normals {
+        Some(normals) => normals,
+        None => {
+            positions = unweld(&indices, &positions);
+            uvs = unweld(&indices, &uvs);
+            indices = (0..indices.len() as u32).collect();
+            flat_normals(&positions)
+        }
+    };
+    let tangents = tangents::generate_tangents(&indices, &positions, &normals, &uvs);
+
+    dst.meshes.push(Mesh {
+        indices: dst.indices.len() as u32,
+        indices_count: indices.len() as u32,
+        positions: dst.positions.len() as u32,
+        normals: dst.normals.len() as u32,
+        tangents: dst.tangents.len() as u32,
+        uvs: dst.uvs.len() as u32,
+    });
+    dst.indices.extend(indices);
+    dst.positions.extend(positions);
+    dst.normals.extend(normals);
+    dst.tangents.extend(tangents);
+    dst.uvs.extend(uvs);
+    Ok(dst.meshes.len() - 1)
 }
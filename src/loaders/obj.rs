@@ -0,0 +1,376 @@
+use crate::common::*;
+use crate::scene::Scene;
+use anyhow::{bail, Context, Result};
+use glam::*;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::path::Path;
+
+use super::{append_mesh, Loader};
+
+///
+/// Loads Wavefront OBJ files and their MTL materials.
+/// Every group becomes a mesh with one instance, the Phong parameters of the materials are
+/// approximated with the metallic roughness model.
+///
+#[derive(Default)]
+pub struct ObjLoader {}
+
+impl Loader<Scene> for ObjLoader {
+    fn append(&self, path: impl AsRef<Path>, dst: &mut Scene) -> Result<usize> {
+        let path = path.as_ref();
+        let (models, materials) = tobj::load_obj(
+            path,
+            &tobj::LoadOptions {
+                single_index: true,
+                triangulate: true,
+                ..Default::default()
+            },
+        )
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+        // A missing material library is common in downloaded files, its groups fall back to the
+        // default material.
+        let materials = match materials {
+            Err(tobj::LoadError::OpenFileFailed) => {
+                eprintln!(
+                    "Could not open the materials of {}, using the default material",
+                    path.display()
+                );
+                vec![]
+            }
+            materials => materials
+                .with_context(|| format!("Failed to load materials of {}", path.display()))?,
+        };
+
+        // Texture paths are relative to the OBJ file, files used by several materials are only
+        // loaded once.
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut textures = HashMap::new();
+        let mut texture = |file: &str, dst: &mut Scene| -> Result<Option<Texture>> {
+            if file.is_empty() {
+                return Ok(None);
+            }
+            if let Some(&index) = textures.get(file) {
+                return Ok(Some(Texture::image(index)));
+            }
+            let img_path = dir.join(file.replace('\\', "/"));
+            let img = image::open(&img_path)
+                .with_context(|| format!("Failed to load {}", img_path.display()))?;
+            let index = dst.textures.len() as u32;
+            dst.textures
+                .push(image::DynamicImage::ImageRgba8(img.into_rgba8()));
+            textures.insert(file.to_owned(), index);
+            Ok(Some(Texture::image(index)))
+        };
+
+        let material_offset = dst.materials.len();
+        let mut emissive = vec![];
+        for material in materials.iter() {
+            let emission_factor = material
+                .unknown_param
+                .get("Ke")
+                .map(|ke| parse_vec3(ke))
+                .transpose()
+                .with_context(|| format!("Invalid Ke in material {}", material.name))?
+                .unwrap_or(Vec3::ZERO);
+            let emission = match material.unknown_param.get("map_Ke") {
+                Some(file) => texture(file, dst)?,
+                None => None,
+            };
+
+            let diffuse = Vec3::from(material.diffuse);
+            let specular = Vec3::from(material.specular);
+            // Only specular reflection is treated as metal, mixing diffuse and specular
+            // reflection is left to the dielectric Fresnel term.
+            let metallic = if diffuse == Vec3::ZERO && specular != Vec3::ZERO {
+                1.
+            } else {
+                0.
+            };
+            // The Beckmann alpha matching the Phong exponent (Walter et al. 2007) is the square of
+            // the roughness.
+            let roughness = (2. / (material.shininess.max(0.) + 2.)).sqrt().sqrt();
+
+            let base_color = texture(&material.diffuse_texture, dst)?.unwrap_or(Texture::constant(
+                if metallic > 0. { specular } else { diffuse },
+            ));
+            // Only `norm` is a tangent space normal map, tobj reads the height maps of `bump` into
+            // `normal_texture`.
+            let normal = match material
+                .unknown_param
+                .get("norm")
+                .or(material.unknown_param.get("map_Kn"))
+            {
+                Some(file) => texture(file, dst)?,
+                None => None,
+            };
+
+            dst.materials.push(Material {
+                base_color,
+                metallic_roughness: Texture::constant(vec3(0., roughness, metallic)),
+                normal: normal.unwrap_or(Texture::constant(vec3(0., 0., 1.))),
+                ior: if material.optical_density > 0. {
+                    material.optical_density
+                } else {
+                    1.5
+                },
+                emission: emission.unwrap_or(Texture::constant(Vec3::ONE)),
+                emission_factor,
+                ..Default::default()
+            });
+            emissive.push(emission_factor != Vec3::ZERO);
+        }
+        // Groups without material use a grey diffuse material, added on demand.
+        let mut default_material = None;
+
+        let instance_offset = dst.instances.len();
+        let (mut min, mut max) = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+        for model in models.iter() {
+            let mesh = &model.mesh;
+            if mesh.indices.is_empty() {
+                continue;
+            }
+
+            let positions = mesh
+                .positions
+                .chunks_exact(3)
+                .map(Vec3::from_slice)
+                .collect::<Vec<_>>();
+            for p in positions.iter() {
+                min = min.min(*p);
+                max = max.max(*p);
+            }
+            let normals = (!mesh.normals.is_empty())
+                .then(|| mesh.normals.chunks_exact(3).map(Vec3::from_slice).collect());
+            let uvs = obj_uvs(mesh, positions.len());
+            let mesh_index = append_mesh(dst, mesh.indices.clone(), positions, normals, uvs)
+                .with_context(|| format!("Invalid group {} in {}", model.name, path.display()))?;
+
+            let material_id = mesh.material_id.filter(|&id| id < materials.len());
+            let material = match material_id {
+                Some(id) => (material_offset + id) as u32,
+                None => *default_material.get_or_insert_with(|| {
+                    dst.materials.push(Material {
+                        base_color: Texture::constant(Vec3::splat(0.8)),
+                        metallic_roughness: Texture::constant(vec3(0., 1., 0.)),
+                        normal: Texture::constant(vec3(0., 0., 1.)),
+                        ..Default::default()
+                    });
+                    dst.materials.len() as u32 - 1
+                }),
+            };
+
+            let instance = dst.instances.len();
+            let mut emitter = -1;
+            if material_id.is_some_and(|id| emissive[id]) {
+                emitter = dst.emitters.len() as _;
+                dst.emitters.push(Emitter::area(instance as u32));
+            }
+            dst.instances.push(Instance {
+                to_world: Mat4::IDENTITY,
+                mesh: mesh_index as u32,
+                material,
+                emitter,
+            });
+        }
+
+        // OBJ has no cameras, look at the bounding box along -z if the scene has none.
+        if dst.cameras.is_empty() && min.cmple(max).all() {
+            let fov_y = PI / 4.;
+            let center = (min + max) / 2.;
+            let radius = ((max - min).length() / 2.).max(0.001);
+            let eye = center + Vec3::Z * (radius / (fov_y / 2.).sin());
+            let to_world = Mat4::look_at_rh(eye, center, Vec3::Y).inverse();
+            dst.cameras
+                .push(Camera::perspective(to_world, fov_y, 1., 0.001, 10000.));
+        }
+
+        Ok(instance_offset)
+    }
+}
+
+///
+/// Texture coordinates of an OBJ mesh with the origin at the top left, zero if it has none.
+///
+pub(super) fn obj_uvs(mesh: &tobj::Mesh, count: usize) -> Vec<Vec2> {
+    if mesh.texcoords.is_empty() {
+        return vec![Vec2::ZERO; count];
+    }
+    // OBJ texture coordinates start at the bottom left.
+    mesh.texcoords
+        .chunks_exact(2)
+        .map(|uv| vec2(uv[0], 1. - uv[1]))
+        .collect()
+}
+
+///
+/// Parses three whitespace separated floats, as used for colors in MTL files.
+///
+fn parse_vec3(s: &str) -> Result<Vec3> {
+    let v = s
+        .split_whitespace()
+        .map(|x| x.parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    match v[..] {
+        [x, y, z] => Ok(vec3(x, y, z)),
+        [x] => Ok(Vec3::splat(x)),
+        _ => bail!("Expected 3 components, found {}", v.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Writes `files` and a normal map into a directory of their own and loads the first file.
+    ///
+    fn load(test: &str, files: &[(&str, &str)]) -> Result<Scene> {
+        let dir = std::env::temp_dir().join(format!("obj-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            std::fs::write(dir.join(name), text).unwrap();
+        }
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255]))
+            .save(dir.join("normal.png"))
+            .unwrap();
+        let mut scene = Scene::default();
+        let result = ObjLoader::default().append(dir.join(files[0].0), &mut scene);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.map(|_| scene)
+    }
+
+    const QUADS: &str = "mtllib quads.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 -2
+v 1 0 -2
+v 1 1 -2
+v 0 1 -2
+g plain
+f 1 2 3
+g red
+usemtl red
+f 1 2 3 4
+g light
+usemtl light
+f 5 6 7 8
+";
+
+    #[test]
+    fn groups_use_their_materials() {
+        let mtl = "newmtl red
+Kd 1 0 0
+Ks 0 0 0
+Ns 0
+newmtl light
+Kd 0 0 0
+Ks 1 1 1
+Ns 1000
+Ke 2 2 2
+";
+        let scene = load("materials", &[("quads.obj", QUADS), ("quads.mtl", mtl)]).unwrap();
+        assert_eq!(scene.instances.len(), 3);
+        // Two materials and the default one.
+        assert_eq!(scene.materials.len(), 3);
+
+        let plain = &scene.materials[scene.instances[0].material as usize];
+        assert_eq!(plain.base_color.val, Vec3::splat(0.8));
+        assert_eq!(scene.instances[0].emitter, -1);
+
+        let red = &scene.materials[scene.instances[1].material as usize];
+        assert_eq!(red.base_color.val, vec3(1., 0., 0.));
+        // Phong exponent 0 is as rough as it gets, diffuse materials are not metallic.
+        assert_eq!(red.metallic_roughness.val, vec3(0., 1., 0.));
+
+        let light = &scene.materials[scene.instances[2].material as usize];
+        assert_eq!(light.base_color.val, Vec3::ONE);
+        assert_eq!(light.metallic_roughness.val.z, 1.);
+        assert!(light.metallic_roughness.val.y < 0.3);
+        assert_eq!(light.emission_factor, Vec3::splat(2.));
+        assert_eq!(scene.instances[2].emitter, 0);
+        assert_eq!(scene.emitters.len(), 1);
+        assert_eq!(scene.emitters[0].instance, 2);
+
+        // Quads are triangulated.
+        assert_eq!(scene.meshes[0].indices_count, 3);
+        assert_eq!(scene.meshes[1].indices_count, 6);
+    }
+
+    #[test]
+    fn missing_material_library_uses_default_material() {
+        let scene = load("missing-mtl", &[("quads.obj", QUADS)]).unwrap();
+        assert_eq!(scene.instances.len(), 3);
+        assert_eq!(scene.materials.len(), 1);
+        assert!(scene.instances.iter().all(|i| i.material == 0));
+        assert!(scene.emitters.is_empty());
+    }
+
+    #[test]
+    fn malformed_material_library_is_an_error() {
+        let mtl = "newmtl red\nKd one two three\n";
+        assert!(load("bad-mtl", &[("quads.obj", QUADS), ("quads.mtl", mtl)]).is_err());
+    }
+
+    #[test]
+    fn bump_maps_are_not_normal_maps() {
+        let mtl = "newmtl red
+Kd 1 0 0
+map_Bump height.png
+newmtl light
+Kd 1 1 1
+norm normal.png
+";
+        let scene = load("normal", &[("quads.obj", QUADS), ("quads.mtl", mtl)]).unwrap();
+
+        let red = &scene.materials[scene.instances[1].material as usize];
+        assert_eq!(red.normal.ty, Texture::TY_CONSTANT);
+        assert_eq!(red.normal.val, Vec3::Z);
+        let light = &scene.materials[scene.instances[2].material as usize];
+        assert_eq!(light.normal.ty, Texture::TY_IMAGE);
+        assert_eq!(scene.textures.len(), 1);
+    }
+
+    #[test]
+    fn default_camera_sees_the_bounds() {
+        let scene = load("camera", &[("quads.obj", QUADS)]).unwrap();
+        assert_eq!(scene.cameras.len(), 1);
+        let to_world = scene.cameras[0].to_world;
+        // Looking along -z at the center of the bounds.
+        let center = vec3(0.5, 0.5, -1.);
+        let eye = to_world.transform_point3(Vec3::ZERO);
+        assert!(eye.truncate().abs_diff_eq(center.truncate(), 1e-5) && eye.z > 0.);
+        let forward = to_world.transform_vector3(-Vec3::Z);
+        assert!(forward.abs_diff_eq(-Vec3::Z, 1e-5));
+        // The bounding sphere fits into the vertical field of view.
+        let radius = vec3(1., 1., 2.).length() / 2.;
+        let distance = eye.distance(center);
+        assert!((radius / distance - (PI / 8.).sin()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn invalid_meshes_are_an_error() {
+        let mut scene = Scene::default();
+        let positions = vec![Vec3::ZERO, Vec3::X, Vec3::Y];
+        let uvs = vec![Vec2::ZERO; 3];
+        assert!(append_mesh(
+            &mut scene,
+            vec![0, 1, 3],
+            positions.clone(),
+            None,
+            uvs.clone()
+        )
+        .is_err());
+        assert!(append_mesh(
+            &mut scene,
+            vec![0, 1, 2],
+            positions,
+            Some(vec![Vec3::Z; 2]),
+            uvs
+        )
+        .is_err());
+        assert!(scene.meshes.is_empty());
+    }
+}
@@ -0,0 +1,365 @@
+//! Minimal reader for triangle meshes in the PLY format (ascii and binary), as used by the
+//! Mitsuba scenes.
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use glam::*;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct PlyMesh {
+    pub indices: Vec<u32>,
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<Vec2>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar {
+        ty: String,
+        name: String,
+    },
+    List {
+        count: String,
+        item: String,
+        name: String,
+    },
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+///
+/// Reads values of the body, ascii bodies are read token by token.
+///
+struct Body<'a> {
+    encoding: Encoding,
+    data: &'a [u8],
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl Body<'_> {
+    fn read(&mut self, ty: &str) -> Result<f64> {
+        if self.encoding == Encoding::Ascii {
+            let token = self
+                .tokens
+                .next()
+                .ok_or_else(|| anyhow!("Unexpected end of file"))?;
+            return Ok(token.parse::<f64>()?);
+        }
+        let size = match ty {
+            "char" | "int8" | "uchar" | "uint8" => 1,
+            "short" | "int16" | "ushort" | "uint16" => 2,
+            "int" | "int32" | "uint" | "uint32" | "float" | "float32" => 4,
+            "double" | "float64" => 8,
+            _ => bail!("Unknown property type {ty}"),
+        };
+        ensure!(self.data.len() >= size, "Unexpected end of file");
+        let (bytes, rest) = self.data.split_at(size);
+        self.data = rest;
+
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(bytes);
+        if self.encoding == Encoding::BigEndian {
+            buf[..size].reverse();
+        }
+        Ok(match ty {
+            "char" | "int8" => buf[0] as i8 as f64,
+            "uchar" | "uint8" => buf[0] as f64,
+            "short" | "int16" => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+            "ushort" | "uint16" => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+            "int" | "int32" => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            "uint" | "uint32" => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            "float" | "float32" => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            _ => f64::from_le_bytes(buf),
+        })
+    }
+}
+
+///
+/// Loads the vertices and faces of a PLY file, polygons are triangulated as fans.
+///
+pub fn load_ply(path: &Path) -> Result<PlyMesh> {
+    let file = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    const END_HEADER: &[u8] = b"end_header";
+    let end = file
+        .windows(END_HEADER.len())
+        .position(|w| w == END_HEADER)
+        .ok_or_else(|| anyhow!("{} has no PLY header", path.display()))?;
+    let body_start = end
+        + file[end..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| anyhow!("{} has no PLY body", path.display()))?
+        + 1;
+    let header = std::str::from_utf8(&file[..end])?;
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+    for line in header.lines() {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["format", format, _] => {
+                encoding = Some(match format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::LittleEndian,
+                    "binary_big_endian" => Encoding::BigEndian,
+                    _ => bail!("Unknown PLY format {format}"),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_owned(),
+                count: count.parse()?,
+                properties: vec![],
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| anyhow!("Property outside of an element"))?
+                .properties
+                .push(Property::List {
+                    count: count.to_owned(),
+                    item: item.to_owned(),
+                    name: name.to_owned(),
+                }),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or_else(|| anyhow!("Property outside of an element"))?
+                .properties
+                .push(Property::Scalar {
+                    ty: ty.to_owned(),
+                    name: name.to_owned(),
+                }),
+            _ => {}
+        }
+    }
+    let encoding = encoding.ok_or_else(|| anyhow!("{} has no format", path.display()))?;
+
+    let data = &file[body_start..];
+    let mut body = Body {
+        encoding,
+        data,
+        tokens: match encoding {
+            Encoding::Ascii => std::str::from_utf8(data)?.split_ascii_whitespace(),
+            _ => "".split_ascii_whitespace(),
+        },
+    };
+
+    let mut mesh = PlyMesh::default();
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    for element in elements.iter() {
+        for _ in 0..element.count {
+            let mut p = Vec3::ZERO;
+            let mut n = Vec3::ZERO;
+            let mut uv = Vec2::ZERO;
+            for property in element.properties.iter() {
+                match property {
+                    Property::Scalar { ty, name } => {
+                        let val = body.read(ty)? as f32;
+                        match name.as_str() {
+                            "x" => p.x = val,
+                            "y" => p.y = val,
+                            "z" => p.z = val,
+                            "nx" => n.x = val,
+                            "ny" => n.y = val,
+                            "nz" => n.z = val,
+                            "u" | "s" | "texture_u" | "texture_s" => uv.x = val,
+                            "v" | "t" | "texture_v" | "texture_t" => uv.y = val,
+                            _ => {}
+                        }
+                    }
+                    Property::List { count, item, name } => {
+                        let count = body.read(count)? as usize;
+                        let mut polygon = Vec::with_capacity(count);
+                        for _ in 0..count {
+                            polygon.push(body.read(item)? as u32);
+                        }
+                        if element.name == "face"
+                            && (name == "vertex_indices" || name == "vertex_index")
+                        {
+                            for i in 2..polygon.len() {
+                                mesh.indices
+                                    .extend([polygon[0], polygon[i - 1], polygon[i]]);
+                            }
+                        }
+                    }
+                }
+            }
+            if element.name == "vertex" {
+                mesh.positions.push(p);
+                normals.push(n);
+                // PLY texture coordinates start at the bottom left.
+                uvs.push(vec2(uv.x, 1. - uv.y));
+            }
+        }
+    }
+
+    let vertex = elements.iter().find(|e| e.name == "vertex");
+    let has = |names: &[&str]| {
+        vertex.is_some_and(|e| {
+            e.properties.iter().any(|p| match p {
+                Property::Scalar { name, .. } => names.contains(&name.as_str()),
+                _ => false,
+            })
+        })
+    };
+    if has(&["nx"]) {
+        mesh.normals = Some(normals);
+    }
+    if has(&["u", "s", "texture_u", "texture_s"]) {
+        mesh.uvs = Some(uvs);
+    }
+    ensure!(
+        mesh.indices
+            .iter()
+            .all(|&i| (i as usize) < mesh.positions.len()),
+        "{} has out of range vertex indices",
+        path.display()
+    );
+
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(test: &str, data: &[u8]) -> Result<PlyMesh> {
+        let path = std::env::temp_dir().join(format!("ply-{test}-{}.ply", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let result = load_ply(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    const QUAD: [Vec3; 4] = [
+        vec3(0., 0., 0.),
+        vec3(1., 0., 0.),
+        vec3(1., 1., 0.),
+        vec3(0., 1., 0.),
+    ];
+
+    ///
+    /// The unit quad as one polygon with float positions and int indices.
+    ///
+    fn binary(format: &str, to_bytes: fn(f32) -> [u8; 4], index: fn(u32) -> [u8; 4]) -> Vec<u8> {
+        let mut data = format!(
+            "ply\nformat {format} 1.0\nelement vertex 4\nproperty float x\nproperty float y\n\
+             property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n"
+        )
+        .into_bytes();
+        for p in QUAD {
+            data.extend(p.to_array().into_iter().flat_map(to_bytes));
+        }
+        data.push(4);
+        data.extend((0..4).flat_map(index));
+        data
+    }
+
+    #[test]
+    fn ascii_with_normals_and_uvs() {
+        let mesh = load(
+            "ascii",
+            b"ply
+format ascii 1.0
+comment made by hand
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float u
+property float v
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1 0 0
+1 0 0 0 0 1 1 0
+1 1 0 0 0 1 1 1
+0 1 0 0 0 1 0 1
+4 0 1 2 3
+",
+        )
+        .unwrap();
+        assert_eq!(mesh.positions, QUAD);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.normals, Some(vec![Vec3::Z; 4]));
+        // Flipped to start at the top left.
+        assert_eq!(mesh.uvs.unwrap()[1], vec2(1., 1.));
+    }
+
+    #[test]
+    fn binary_little_and_big_endian() {
+        for data in [
+            binary("binary_little_endian", f32::to_le_bytes, u32::to_le_bytes),
+            binary("binary_big_endian", f32::to_be_bytes, u32::to_be_bytes),
+        ] {
+            let mesh = load("binary", &data).unwrap();
+            assert_eq!(mesh.positions, QUAD);
+            assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+            assert!(mesh.normals.is_none() && mesh.uvs.is_none());
+        }
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let mesh = load(
+            "fan",
+            b"ply
+format ascii 1.0
+element vertex 5
+property float x
+property float y
+property float z
+element face 2
+property list uchar uint vertex_index
+end_header
+0 0 0
+1 0 0
+1 1 0
+0 1 0
+-1 1 0
+5 0 1 2 3 4
+3 4 3 2
+",
+        )
+        .unwrap();
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3, 0, 3, 4, 4, 3, 2]);
+    }
+
+    #[test]
+    fn out_of_range_indices_are_an_error() {
+        let data = b"ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0
+1 0 0
+1 1 0
+3 0 1 3
+";
+        let err = load("range", data).unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{err}");
+        // Truncated files fail instead of reading zeros.
+        let data = binary("binary_little_endian", f32::to_le_bytes, u32::to_le_bytes);
+        assert!(load("truncated", &data[..data.len() - 2]).is_err());
+    }
+}
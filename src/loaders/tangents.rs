@@ -0,0 +1,58 @@
+use glam::*;
+
+struct MikkGeometry<'a> {
+    indices: &'a [u32],
+    positions: &'a [Vec3],
+    normals: &'a [Vec3],
+    uvs: &'a [Vec2],
+    tangents: Vec<Vec4>,
+}
+
+impl<'a> MikkGeometry<'a> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.indices[face * 3 + vert] as usize
+    }
+}
+
+impl<'a> mikktspace::Geometry for MikkGeometry<'a> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.positions[self.vertex(face, vert)].to_array()
+    }
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.normals[self.vertex(face, vert)].to_array()
+    }
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.uvs[self.vertex(face, vert)].to_array()
+    }
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        let vertex = self.vertex(face, vert);
+        self.tangents[vertex] = Vec4::from(tangent);
+    }
+}
+
+///
+/// Generates MikkTSpace tangents for an indexed triangle mesh.
+/// Indices are relative to the start of `positions`, `normals` and `uvs`.
+///
+pub fn generate_tangents(
+    indices: &[u32],
+    positions: &[Vec3],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+) -> Vec<Vec4> {
+    let mut geometry = MikkGeometry {
+        indices,
+        positions,
+        normals,
+        uvs,
+        tangents: vec![vec4(1., 0., 0., 1.); positions.len()],
+    };
+    mikktspace::generate_tangents(&mut geometry);
+    geometry.tangents
+}
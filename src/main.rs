@@ -1,61 +1,186 @@
 mod accel;
+mod animation;
 mod array;
+#[cfg(test)]
+mod bsdf;
 mod common;
+mod controls;
+mod deform;
+mod distribution;
+mod headless;
+mod light_bvh;
 mod loaders;
 mod post;
 mod renderer;
 mod sbt;
 mod scene;
+mod settings;
+mod ui;
 
+use clap::{CommandFactory, FromArgMatches};
 use crevice::std140::AsStd140;
 use screen_13::prelude::*;
+use screen_13_egui::Egui;
 use std::sync::Arc;
-use winit::event::DeviceEvent;
 
-use self::loaders::Loader;
+use self::controls::FlyCamera;
 use self::post::{Denoiser, LinearToSrgb};
-use self::renderer::{PTRenderer, RestirRenderer};
-use self::scene::Scene;
+use self::renderer::Renderer;
+use self::settings::Settings;
+use self::ui::SceneStats;
 use glam::*;
 
-fn main() -> Result<(), DisplayError> {
+fn main() -> anyhow::Result<()> {
     // pretty_env_logger::init();
-    let sc13 = EventLoop::new().debug(false).build()?;
+    let matches = Settings::command().get_matches();
+    let mut settings = Settings::from_arg_matches(&matches)?;
+
+    let mut scene = loaders::load_scene(&settings.scenes)?;
+    if let Some(film) = &scene.film {
+        settings.apply_film(film, &matches);
+    }
+    scene.animate(settings.clip, settings.time);
+    anyhow::ensure!(
+        (settings.camera as usize) < scene.cameras.len(),
+        "Camera {} does not exist, the scene has {} cameras",
+        settings.camera,
+        scene.cameras.len()
+    );
+
+    if let Some(output) = &settings.output {
+        let device = Arc::new(Device::create_headless(DeviceInfo::default())?);
+        return headless::render(&device, scene, output, &settings);
+    }
+
+    let sc13 = EventLoop::new()
+        .debug(false)
+        .window(|window| {
+            window.with_inner_size(winit::dpi::PhysicalSize::new(
+                settings.width,
+                settings.height,
+            ))
+        })
+        .build()?;
     let device = sc13.device.clone();
     let mut cache = HashPool::new(&device);
 
     let presenter = screen_13_fx::GraphicPresenter::new(&device)?;
+    let mut egui = Egui::new(&device, sc13.as_ref());
 
-    let mut scene = Scene::default();
-    let loader = loaders::GltfLoader::default();
-    loader.append("assets/cornell-box.gltf", &mut scene);
+    scene.set_aspect_ratio(settings.width as f32 / settings.height as f32);
+    let mut stats = SceneStats::new(&scene);
 
-    let mut pt_renderer = PTRenderer::new(&device);
-    let denoiser = Denoiser::new(&device, 1024, 1024);
+    let mut renderer = Renderer::new(&device, &settings);
+    let mut denoiser = Denoiser::new(&device, settings.width, settings.height);
     let linear_to_srgb = LinearToSrgb::new(&device);
 
+    let mut fly_camera = FlyCamera::default();
+
     let mut i = 0;
+    // Number of frames accumulated since the last reset.
+    let mut frame_count = 0;
+    // Animation clip the scene is posed with, the control panel can select another one.
+    let mut clip = settings.clip;
 
     sc13.run(|frame| {
+        // A minimized window has no extent, keep the buffers until it is restored.
+        let minimized = frame.width == 0 || frame.height == 0;
+        if !minimized && (frame.width != settings.width || frame.height != settings.height) {
+            settings.width = frame.width;
+            settings.height = frame.height;
+
+            renderer.resize(frame.width, frame.height);
+            denoiser.resize(&device, frame.width, frame.height);
+            scene.set_aspect_ratio(frame.width as f32 / frame.height as f32);
+            if i != 0 {
+                scene.update_camera(&device, &mut cache, frame.render_graph);
+            }
+            frame_count = 0;
+        }
+        if fly_camera.update(
+            frame.events,
+            frame.dt,
+            &mut scene.cameras[settings.camera as usize].to_world,
+        ) {
+            scene.update_camera(&device, &mut cache, frame.render_graph);
+            frame_count = 0;
+        }
         if i == 0 {
             scene.update(&device, &mut cache, frame.render_graph);
+        } else if let Some(memory) = scene.compact_blases(&device, &mut cache, frame.render_graph) {
+            // The graph of the first frame has been submitted, compact once its queries finished.
+            stats.blas_memory = Some(memory);
         }
-        let scene = scene.bind(frame.render_graph);
-
-        let gbuffer =
-            pt_renderer.bind_and_render(&scene, i, 1024, 1024, 0, &mut cache, frame.render_graph);
+        let advance = settings.play && i != 0;
+        if advance {
+            settings.time += frame.dt;
+        }
+        if advance || clip != settings.clip {
+            clip = settings.clip;
+            if scene.animate(clip, settings.time) {
+                scene.update_camera(&device, &mut cache, frame.render_graph);
+                frame_count = 0;
+            }
+        }
+        if scene.update_deformation(&device, &mut cache, frame.render_graph) {
+            frame_count = 0;
+        }
+        if scene.update_instances(&device, &mut cache, frame.render_graph) {
+            frame_count = 0;
+        }
+        let scene_binding = scene.bind(frame.render_graph);
 
-        let denoised = denoiser.denoise(gbuffer.color, i, frame.render_graph);
+        let denoised = if settings.spp.map(|spp| frame_count < spp).unwrap_or(true) {
+            let color = renderer.bind_and_render(
+                &scene_binding,
+                settings.seed.wrapping_add(i),
+                &settings,
+                &mut cache,
+                frame.render_graph,
+            );
+            let denoised = denoiser.denoise(color, frame_count, frame.render_graph);
+            frame_count += 1;
+            denoised
+        } else {
+            frame.render_graph.bind_node(denoiser.image())
+        };
 
-        let img_srgb = linear_to_srgb.record(denoised, &mut cache, frame.render_graph);
+        let img_srgb =
+            linear_to_srgb.record(denoised, settings.exposure, &mut cache, frame.render_graph);
 
         presenter.present_image(frame.render_graph, img_srgb, frame.swapchain_image);
 
+        let mut response = ui::UiResponse::default();
+        egui.run(
+            frame.window,
+            frame.events,
+            frame.swapchain_image,
+            frame.render_graph,
+            |ctx| {
+                response = ui::control_panel(ctx, &mut settings, &stats, frame.dt, frame_count);
+            },
+        );
+        if response.renderer_changed {
+            renderer = Renderer::new(&device, &settings);
+        }
+        if response.reset {
+            frame_count = 0;
+        }
+        if response.save_screenshot {
+            let path = format!("screenshot-{}", i);
+            if let Err(err) =
+                headless::save(&device, &mut cache, &denoiser, path.as_ref(), &settings)
+            {
+                eprintln!("Could not save screenshot: {err}");
+            }
+        }
+
         // frame
         //     .render_graph
         //     .clear_color_image_value(frame.swapchain_image, [100u8, 149, 237, 255]);
         i += 1;
-    })
+    })?;
+    Ok(())
 }
 //
 // fn main() {
@@ -10,18 +10,7 @@ pub struct Denoiser {
 impl Denoiser {
     pub fn new(device: &Arc<Device>, width: u32, height: u32) -> Self {
         Self {
-            avg: Arc::new(
-                Image::create(
-                    device,
-                    ImageInfo::new_2d(
-                        vk::Format::R32G32B32A32_SFLOAT,
-                        width,
-                        height,
-                        vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
-                    ),
-                )
-                .unwrap(),
-            ),
+            avg: Self::create_avg(device, width, height),
             ppl: Arc::new(
                 ComputePipeline::create(
                     device,
@@ -34,6 +23,32 @@ impl Denoiser {
             ),
         }
     }
+    fn create_avg(device: &Arc<Device>, width: u32, height: u32) -> Arc<Image> {
+        Arc::new(
+            Image::create(
+                device,
+                ImageInfo::new_2d(
+                    vk::Format::R32G32B32A32_SFLOAT,
+                    width,
+                    height,
+                    vk::ImageUsageFlags::STORAGE
+                        | vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC,
+                ),
+            )
+            .unwrap(),
+        )
+    }
+    ///
+    /// Reallocates the accumulation image, the next call to `denoise` should start with
+    /// `frame_count` 0.
+    ///
+    pub fn resize(&mut self, device: &Arc<Device>, width: u32, height: u32) {
+        self.avg = Self::create_avg(device, width, height);
+    }
+    pub fn image(&self) -> &Arc<Image> {
+        &self.avg
+    }
     pub fn denoise(
         &self,
         current: impl Into<AnyImageNode>,
@@ -99,16 +114,18 @@ impl LinearToSrgb {
     pub fn record(
         &self,
         src: impl Into<AnyImageNode>,
+        exposure: f32,
         cache: &mut HashPool,
         rgraph: &mut RenderGraph,
     ) -> AnyImageNode {
         let src = src.into();
+        let src_info = rgraph.node_info(src);
 
         let dst = cache
             .lease(ImageInfo::new_2d(
                 vk::Format::R32G32B32A32_SFLOAT,
-                1024,
-                1024,
+                src_info.width,
+                src_info.height,
                 vk::ImageUsageFlags::STORAGE
                     | vk::ImageUsageFlags::SAMPLED
                     | vk::ImageUsageFlags::COLOR_ATTACHMENT,
@@ -116,12 +133,20 @@ impl LinearToSrgb {
             .unwrap();
         let dst = rgraph.bind_node(dst);
 
+        #[derive(AsStd140)]
+        struct PushConstant {
+            exposure: f32,
+        }
+
+        let push_constant = PushConstant { exposure };
+
         rgraph
             .begin_pass("linear_to_srgb")
             .bind_pipeline(&self.ppl)
             .read_descriptor((0, 0), src)
             .store_color(0, dst)
             .record_subpass(move |subpass, _| {
+                subpass.push_constants(push_constant.as_std140().as_bytes());
                 subpass.draw(6, 1, 0, 0);
             });
 
@@ -2,6 +2,7 @@ use crate::array::Array;
 use crate::common::{RestirReservoir, RestirSample};
 use crate::sbt::{SbtBuffer, SbtBufferInfo};
 use crate::scene::{Scene, SceneBinding};
+use crate::settings::{RendererType, Settings};
 use crevice::std140::AsStd140;
 use screen_13::prelude::*;
 use std::fmt::Write;
@@ -93,9 +94,7 @@ impl PTRenderer {
         scene: &SceneBinding,
         //image: impl Into<AnyImageNode>,
         seed: u32,
-        width: u32,
-        height: u32,
-        camera: u32,
+        settings: &Settings,
         cache: &mut HashPool,
         rgraph: &mut RenderGraph,
     ) -> GBuffer {
@@ -107,11 +106,13 @@ impl PTRenderer {
             pub seed: u32,
         }
         let push_constant = PushConstant {
-            camera,
+            camera: settings.camera,
             seed,
-            max_depth: 8,
-            rr_depth: 2,
+            max_depth: settings.max_depth,
+            rr_depth: settings.rr_depth,
         };
+        let width = settings.width;
+        let height = settings.height;
 
         let mut lease_img = || -> AnyImageNode {
             let img = cache
@@ -141,7 +142,13 @@ impl PTRenderer {
             .read_descriptor((0, 6), scene.emitters)
             .read_descriptor((0, 7), scene.materials)
             .read_descriptor((0, 8), scene.cameras)
-            .read_descriptor((0, 10), scene.accel);
+            .read_descriptor((0, 10), scene.accel)
+            .read_descriptor((0, 11), scene.tangents)
+            .read_descriptor((0, 12), scene.env_cdf)
+            .read_descriptor((0, 13), scene.emitter_alias)
+            .read_descriptor((0, 14), scene.light_bvh)
+            .read_descriptor((0, 15), scene.light_bvh_trails)
+            .read_descriptor((0, 16), scene.triangle_cdf);
 
         for (i, texture) in scene.textures.iter().enumerate() {
             pass = pass.read_descriptor((0, 9, [i as _]), *texture);
@@ -197,7 +204,9 @@ pub struct RestirRenderer{
     do_spatiotemporal: bool,
 }
 impl RestirRenderer{
-    pub fn new(device: &Arc<Device>, width: usize, height: usize) -> Self{
+    pub fn new(device: &Arc<Device>, settings: &Settings) -> Self{
+        let width = settings.width as usize;
+        let height = settings.height as usize;
 
         let initial_ppl = RTPipeline::new(device,
                                           inline_spirv::include_spirv!("src/shaders/path-tracing/integrator/restir-gi/restir-initial.glsl",
@@ -248,11 +257,25 @@ impl RestirRenderer{
         }
     }
 
+    ///
+    /// Reallocates the reservoirs for the new resolution and restarts temporal reuse.
+    ///
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let device = &self.device;
+        self.initial_sample = Array::uninitialized(device, vk::BufferUsageFlags::STORAGE_BUFFER, width * height);
+        self.temporal_reservoir = Array::uninitialized(device, vk::BufferUsageFlags::STORAGE_BUFFER, width * height);
+        self.spatial_reservoir = Array::uninitialized(device, vk::BufferUsageFlags::STORAGE_BUFFER, width * height);
+        self.emittance = Array::uninitialized(device, vk::BufferUsageFlags::STORAGE_BUFFER, width * height);
+        self.width = width;
+        self.height = height;
+        self.do_spatiotemporal = false;
+    }
+
     pub fn bind_and_render(
         &mut self,
         scene: &SceneBinding,
         seed: u32,
-        camera: u32,
+        settings: &Settings,
         cache: &mut HashPool,
         rgraph: &mut RenderGraph,
     ) -> AnyImageNode {
@@ -269,10 +292,10 @@ impl RestirRenderer{
         let width = self.width as u32;
         let height = self.height as u32;
         let mut push_constant = PushConstant {
-            camera,
-            seed: seed * 3,
-            max_depth: 8,
-            rr_depth: 2,
+            camera: settings.camera,
+            seed: seed.wrapping_mul(3),
+            max_depth: settings.max_depth,
+            rr_depth: settings.rr_depth,
             do_spatiotemporal: if self.do_spatiotemporal {1} else {0},
         };
 
@@ -308,6 +331,12 @@ impl RestirRenderer{
             .read_descriptor((0, 7), scene.materials)
             .read_descriptor((0, 8), scene.cameras)
             .read_descriptor((0, 10), scene.accel)
+            .read_descriptor((0, 11), scene.tangents)
+            .read_descriptor((0, 12), scene.env_cdf)
+            .read_descriptor((0, 13), scene.emitter_alias)
+            .read_descriptor((0, 14), scene.light_bvh)
+            .read_descriptor((0, 15), scene.light_bvh_trails)
+            .read_descriptor((0, 16), scene.triangle_cdf)
             .write_descriptor((1, 0), initial_sample)
             .write_descriptor((1, 1), temporal_reservoir)
             .write_descriptor((1, 2), spatial_reservoir)
@@ -335,7 +364,7 @@ impl RestirRenderer{
             );
         });
 
-        push_constant.seed += 1;
+        push_constant.seed = push_constant.seed.wrapping_add(1);
 
         let mut pass = rgraph
             .begin_pass("ReSTIR Temporal Resampling Pass")
@@ -350,6 +379,12 @@ impl RestirRenderer{
             .read_descriptor((0, 7), scene.materials)
             .read_descriptor((0, 8), scene.cameras)
             .read_descriptor((0, 10), scene.accel)
+            .read_descriptor((0, 11), scene.tangents)
+            .read_descriptor((0, 12), scene.env_cdf)
+            .read_descriptor((0, 13), scene.emitter_alias)
+            .read_descriptor((0, 14), scene.light_bvh)
+            .read_descriptor((0, 15), scene.light_bvh_trails)
+            .read_descriptor((0, 16), scene.triangle_cdf)
             .read_descriptor((1, 0), initial_sample)
             .write_descriptor((1, 1), temporal_reservoir)
             .write_descriptor((1, 2), spatial_reservoir);
@@ -376,7 +411,7 @@ impl RestirRenderer{
             );
         });
         
-        push_constant.seed += 1;
+        push_constant.seed = push_constant.seed.wrapping_add(1);
 
         let mut pass = rgraph
             .begin_pass("ReSTIR Spatial Resampling Pass")
@@ -391,6 +426,12 @@ impl RestirRenderer{
             .read_descriptor((0, 7), scene.materials)
             .read_descriptor((0, 8), scene.cameras)
             .read_descriptor((0, 10), scene.accel)
+            .read_descriptor((0, 11), scene.tangents)
+            .read_descriptor((0, 12), scene.env_cdf)
+            .read_descriptor((0, 13), scene.emitter_alias)
+            .read_descriptor((0, 14), scene.light_bvh)
+            .read_descriptor((0, 15), scene.light_bvh_trails)
+            .read_descriptor((0, 16), scene.triangle_cdf)
             .read_descriptor((1, 0), initial_sample)
             .write_descriptor((1, 1), temporal_reservoir)
             .write_descriptor((1, 2), spatial_reservoir);
@@ -417,7 +458,7 @@ impl RestirRenderer{
             );
         });
         
-        push_constant.seed += 1;
+        push_constant.seed = push_constant.seed.wrapping_add(1);
         
         let mut pass = rgraph
             .begin_pass("ReSTIR Output Pass")
@@ -432,6 +473,12 @@ impl RestirRenderer{
             .read_descriptor((0, 7), scene.materials)
             .read_descriptor((0, 8), scene.cameras)
             //.read_descriptor((0, 10), scene.accel)
+            .read_descriptor((0, 11), scene.tangents)
+            .read_descriptor((0, 12), scene.env_cdf)
+            .read_descriptor((0, 13), scene.emitter_alias)
+            .read_descriptor((0, 14), scene.light_bvh)
+            .read_descriptor((0, 15), scene.light_bvh_trails)
+            .read_descriptor((0, 16), scene.triangle_cdf)
             .read_descriptor((1, 0), initial_sample)
             .read_descriptor((1, 1), temporal_reservoir)
             .read_descriptor((1, 2), spatial_reservoir)
@@ -456,3 +503,39 @@ impl RestirRenderer{
     
 }
 
+pub enum Renderer {
+    PT(PTRenderer),
+    Restir(RestirRenderer),
+}
+
+impl Renderer {
+    pub fn new(device: &Arc<Device>, settings: &Settings) -> Self {
+        match settings.renderer {
+            RendererType::Pt => Self::PT(PTRenderer::new(device)),
+            RendererType::Restir => Self::Restir(RestirRenderer::new(device, settings)),
+        }
+    }
+    pub fn bind_and_render(
+        &mut self,
+        scene: &SceneBinding,
+        seed: u32,
+        settings: &Settings,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
+    ) -> AnyImageNode {
+        match self {
+            Self::PT(renderer) => {
+                renderer
+                    .bind_and_render(scene, seed, settings, cache, rgraph)
+                    .color
+            }
+            Self::Restir(renderer) => renderer.bind_and_render(scene, seed, settings, cache, rgraph),
+        }
+    }
+    pub fn resize(&mut self, width: u32, height: u32) {
+        match self {
+            Self::PT(_) => {}
+            Self::Restir(renderer) => renderer.resize(width as _, height as _),
+        }
+    }
+}
@@ -1,17 +1,34 @@
-use crate::accel::{Blas, Tlas};
+use crate::accel::{Blas, CompactionQuery, Tlas};
+use crate::animation::Animation;
 use crate::array::Array;
 use crate::common::{self, *};
+use crate::deform::{DeformPass, Deformations};
+use crate::distribution::{AliasTable, Distribution1D};
+use crate::light_bvh::{DirectionCone, LightBounds, LightBvh};
 use glam::*;
 use screen_13::prelude::*;
 use screen_13_fx::ImageLoader;
+use std::f32::consts::PI;
 use std::sync::Arc;
 
+///
+/// Image size and sample count requested by the scene description.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub spp: Option<u32>,
+}
+
 #[derive(Default)]
 pub struct Scene {
     //pub device: Arc<Device>,
     pub indices: Vec<u32>,
     pub positions: Vec<Vec3>,
     pub normals: Vec<Vec3>,
+    /// Tangents with the bitangent sign in w.
+    pub tangents: Vec<Vec4>,
     pub uvs: Vec<Vec2>,
     pub textures: Vec<image::DynamicImage>,
 
@@ -20,21 +37,49 @@ pub struct Scene {
     pub emitters: Vec<Emitter>,
     pub materials: Vec<Material>,
     pub cameras: Vec<Camera>,
+    /// Film of the first sensor, only set by scene formats that describe one.
+    pub film: Option<Film>,
+    pub animations: Vec<Animation>,
+    /// Skinned and morphed meshes, posed by the animations.
+    pub deformations: Deformations,
+    /// CDFs of the environment map, see `Distribution2D::cdfs`.
+    pub env_cdf: Vec<f32>,
+    /// Triangle area CDFs of the area emitters, built in `upload`.
+    pub triangle_cdf: Vec<f32>,
+    /// Object space bounds of every mesh, built in `upload`.
+    mesh_bounds: Vec<(Vec3, Vec3)>,
 
     // Components on GPU
+    /// One BLAS per mesh, referenced by every instance of the mesh.
     pub blases: Vec<Blas<Vec3>>,
     pub tlas: Option<Tlas>,
+    /// Compacted blas sizes, pending until `compact_blases` is called.
+    pub compaction: Option<CompactionQuery>,
+    /// Instances changed since the last upload.
+    instances_changed: bool,
+    /// Emitters or the transforms of emissive instances changed since the last upload, the
+    /// emitter distributions have to be rebuilt.
+    emitters_changed: bool,
+    /// Pose of the deformed meshes changed since the last deformation pass.
+    deformation_changed: bool,
+    pub deform_pass: Option<DeformPass>,
 
     pub instance_data: Option<Array<common::Instance>>,
     pub mesh_data: Option<Array<Mesh>>,
     pub emitter_data: Option<Array<Emitter>>,
+    pub emitter_alias_data: Option<Array<AliasEntry>>,
+    pub light_bvh_data: Option<Array<LightBvhNode>>,
+    pub light_bvh_trail_data: Option<Array<u32>>,
     pub material_data: Option<Array<Material>>,
     pub camera_data: Option<Array<Camera>>,
 
     pub index_data: Option<Array<u32>>,
     pub position_data: Option<Array<Vec3>>,
     pub normal_data: Option<Array<Vec3>>,
+    pub tangent_data: Option<Array<Vec4>>,
     pub uv_data: Option<Array<Vec2>>,
+    pub env_cdf_data: Option<Array<f32>>,
+    pub triangle_cdf_data: Option<Array<f32>>,
 
     pub textures_gpu: Option<Vec<Arc<Image>>>,
 }
@@ -50,6 +95,276 @@ impl Scene {
             self.meshes[mesh_idx + 1].indices as usize - self.meshes[mesh_idx].indices as usize
         }
     }
+    ///
+    /// Sets the environment emitter, which is always kept at index 0 of `emitters` so that
+    /// shaders can find it on a miss.
+    ///
+    pub fn set_env(&mut self, env: Emitter, cdf: Vec<f32>) {
+        if self.env().is_some() {
+            self.emitters[0] = env;
+        } else {
+            self.emitters.insert(0, env);
+            for instance in self.instances.iter_mut() {
+                if instance.emitter >= 0 {
+                    instance.emitter += 1;
+                }
+            }
+        }
+        self.env_cdf = cdf;
+    }
+    pub fn env(&self) -> Option<&Emitter> {
+        self.emitters.first().filter(|emitter| emitter.is_env())
+    }
+    ///
+    /// Average value of a texture, used to estimate emitter power.
+    ///
+    fn texture_average(&self, texture: &Texture) -> Vec3 {
+        if texture.ty == Texture::TY_CONSTANT {
+            return texture.val;
+        }
+        let img = self.textures[texture.texture as usize].to_rgb32f();
+        let sum = img
+            .pixels()
+            .fold(Vec3::ZERO, |sum, p| sum + vec3(p[0], p[1], p[2]));
+        sum / (img.width() * img.height()).max(1) as f32
+    }
+    ///
+    /// World space triangles of an instance.
+    ///
+    fn instance_triangles<'a>(
+        &'a self,
+        instance: &'a common::Instance,
+    ) -> impl Iterator<Item = [Vec3; 3]> + 'a {
+        let mesh = &self.meshes[instance.mesh as usize];
+        self.indices[mesh.indices as usize..(mesh.indices + mesh.indices_count) as usize]
+            .chunks_exact(3)
+            .map(move |triangle| {
+                [triangle[0], triangle[1], triangle[2]].map(|i| {
+                    instance
+                        .to_world
+                        .transform_point3(self.positions[(mesh.positions + i) as usize])
+                })
+            })
+    }
+    fn instance_area(&self, instance: &common::Instance) -> f32 {
+        self.instance_triangles(instance)
+            .map(|[p0, p1, p2]| (p1 - p0).cross(p2 - p0).length() / 2.)
+            .sum()
+    }
+    ///
+    /// Builds the CDFs for sampling triangles of area emitters proportional to their world
+    /// space area and stores their offsets and total area in the emitters.
+    ///
+    fn build_triangle_cdfs(&mut self) {
+        self.triangle_cdf.clear();
+        for i in 0..self.emitters.len() {
+            if self.emitters[i].ty != Emitter::TY_AREA {
+                continue;
+            }
+            let instance = &self.instances[self.emitters[i].instance as usize];
+            let areas = self
+                .instance_triangles(instance)
+                .map(|[p0, p1, p2]| (p1 - p0).cross(p2 - p0).length() / 2.)
+                .collect::<Vec<_>>();
+            let area = areas.iter().sum();
+            let distribution = Distribution1D::new(&areas);
+
+            self.emitters[i].triangle_cdf = self.triangle_cdf.len() as u32;
+            self.emitters[i].area = area;
+            self.triangle_cdf.extend(distribution.cdf);
+        }
+    }
+    ///
+    /// Computes the object space bounds of every mesh from the vertices it references.
+    ///
+    fn build_mesh_bounds(&mut self) {
+        self.mesh_bounds = self
+            .meshes
+            .iter()
+            .map(|mesh| {
+                self.indices[mesh.indices as usize..(mesh.indices + mesh.indices_count) as usize]
+                    .iter()
+                    .map(|&i| self.positions[(mesh.positions + i) as usize])
+                    .fold(
+                        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                        |(min, max), p| (min.min(p), max.max(p)),
+                    )
+            })
+            .collect();
+    }
+    ///
+    /// Radius of a sphere around all instances, from the transformed bounds of their meshes.
+    ///
+    fn bounding_radius(&self) -> f32 {
+        let (min, max) = self
+            .instances
+            .iter()
+            .filter_map(|instance| {
+                let (min, max) = self.mesh_bounds[instance.mesh as usize];
+                if min.cmpgt(max).any() {
+                    return None;
+                }
+                let center = instance.to_world.transform_point3((min + max) / 2.);
+                let m = Mat3::from_mat4(instance.to_world);
+                let half = (max - min) / 2.;
+                let extent =
+                    m.x_axis.abs() * half.x + m.y_axis.abs() * half.y + m.z_axis.abs() * half.z;
+                Some((center - extent, center + extent))
+            })
+            .fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), bounds| (min.min(bounds.0), max.max(bounds.1)),
+            );
+        if min.cmple(max).all() {
+            (max - min).length() / 2.
+        } else {
+            0.
+        }
+    }
+    ///
+    /// Bounds of every emitter for the light BVH, `None` for infinite emitters.
+    ///
+    pub fn emitter_bounds(&self) -> Vec<Option<LightBounds>> {
+        self.emitter_powers()
+            .into_iter()
+            .zip(self.emitters.iter())
+            .map(|(power, emitter)| match emitter.ty {
+                Emitter::TY_AREA => {
+                    let instance = &self.instances[emitter.instance as usize];
+                    let (min, max, normals) = self.instance_triangles(instance).fold(
+                        (
+                            Vec3::splat(f32::MAX),
+                            Vec3::splat(f32::MIN),
+                            DirectionCone::EMPTY,
+                        ),
+                        |(min, max, normals), [p0, p1, p2]| {
+                            let n = (p1 - p0).cross(p2 - p0);
+                            let normals = if n.length_squared() > 0. {
+                                normals.union(&DirectionCone::new(n, 1.))
+                            } else {
+                                normals
+                            };
+                            (
+                                min.min(p0).min(p1).min(p2),
+                                max.max(p0).max(p1).max(p2),
+                                normals,
+                            )
+                        },
+                    );
+                    Some(LightBounds {
+                        min,
+                        max,
+                        normals,
+                        cos_theta_e: 0.,
+                        power,
+                        // Area emitters emit on both sides.
+                        two_sided: true,
+                    })
+                }
+                Emitter::TY_POINT => Some(LightBounds {
+                    min: emitter.position,
+                    max: emitter.position,
+                    normals: DirectionCone::SPHERE,
+                    cos_theta_e: 0.,
+                    power,
+                    two_sided: false,
+                }),
+                Emitter::TY_SPOT => Some(LightBounds {
+                    min: emitter.position,
+                    max: emitter.position,
+                    normals: DirectionCone::new(emitter.direction, 1.),
+                    cos_theta_e: emitter.cos_outer_cone,
+                    power,
+                    two_sided: false,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+    ///
+    /// Approximate power of every emitter, emitters are sampled proportional to it.
+    ///
+    pub fn emitter_powers(&self) -> Vec<f32> {
+        let radius = self.bounding_radius();
+        self.emitters
+            .iter()
+            .map(|emitter| match emitter.ty {
+                Emitter::TY_AREA => {
+                    let instance = &self.instances[emitter.instance as usize];
+                    let material = &self.materials[instance.material as usize];
+                    let emission =
+                        self.texture_average(&material.emission) * material.emission_factor;
+                    PI * luminance(emission) * self.instance_area(instance)
+                }
+                Emitter::TY_ENV => {
+                    4. * PI
+                        * PI
+                        * radius
+                        * radius
+                        * luminance(self.texture_average(&emitter.irradiance))
+                }
+                Emitter::TY_POINT => 4. * PI * luminance(emitter.irradiance.val),
+                Emitter::TY_SPOT => {
+                    2. * PI
+                        * luminance(emitter.irradiance.val)
+                        * (1. - 0.5 * (emitter.cos_inner_cone + emitter.cos_outer_cone))
+                }
+                Emitter::TY_DIRECTIONAL => PI * radius * radius * luminance(emitter.irradiance.val),
+                _ => 0.,
+            })
+            .collect()
+    }
+    ///
+    /// Alias table used to choose between the infinite emitters (proportional to their power)
+    /// and the light BVH, which is traversed with the same probability as choosing one of the
+    /// infinite emitters (pbrt-v4).
+    ///
+    fn emitter_alias(&self, light_bvh: &LightBvh) -> AliasTable {
+        let infinite_count = self.emitters.iter().filter(|e| e.is_infinite()).count();
+        let bounded_count = self.emitters.len() - infinite_count;
+        let pdf_bvh = if light_bvh.is_empty() {
+            0.
+        } else {
+            1. / (infinite_count + 1) as f32
+        };
+
+        let powers = self.emitter_powers();
+        let infinite_power = self
+            .emitters
+            .iter()
+            .zip(powers.iter())
+            .filter(|(e, _)| e.is_infinite())
+            .map(|(_, power)| power)
+            .sum::<f32>();
+
+        let weights = self
+            .emitters
+            .iter()
+            .zip(powers.iter())
+            .map(|(emitter, &power)| {
+                if !emitter.is_infinite() {
+                    pdf_bvh / bounded_count as f32
+                } else if infinite_power > 0. {
+                    (1. - pdf_bvh) * power / infinite_power
+                } else {
+                    (1. - pdf_bvh) / infinite_count as f32
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut alias = AliasTable::new(&weights);
+        for (entry, emitter) in alias.entries.iter_mut().zip(self.emitters.iter()) {
+            if !emitter.is_infinite() {
+                entry.pdf = pdf_bvh;
+            }
+        }
+        alias
+    }
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        for camera in self.cameras.iter_mut() {
+            camera.set_aspect_ratio(aspect_ratio);
+        }
+    }
     pub fn update_camera(
         &mut self,
         device: &Arc<Device>,
@@ -90,35 +405,43 @@ impl Scene {
             vk::BufferUsageFlags::STORAGE_BUFFER,
             &self.normals,
         ));
-        self.uv_data = Some(Array::from_slice_staging(
+        self.tangent_data = Some(Array::from_slice_staging(
             &device,
             cache,
             rgraph,
             vk::BufferUsageFlags::STORAGE_BUFFER,
-            &self.uvs,
+            &self.tangents,
         ));
-
-        self.instance_data = Some(Array::from_slice_staging(
+        self.uv_data = Some(Array::from_slice_staging(
             &device,
             cache,
             rgraph,
             vk::BufferUsageFlags::STORAGE_BUFFER,
-            &self.instances,
+            &self.uvs,
         ));
-        self.mesh_data = Some(Array::from_slice_staging(
+        // Bind a dummy entry when there is no environment map.
+        self.env_cdf_data = Some(Array::from_slice_staging(
             &device,
             cache,
             rgraph,
             vk::BufferUsageFlags::STORAGE_BUFFER,
-            &self.meshes,
+            if self.env_cdf.is_empty() {
+                &[0.]
+            } else {
+                &self.env_cdf
+            },
         ));
-        self.emitter_data = Some(Array::from_slice_staging(
+
+        self.mesh_data = Some(Array::from_slice_staging(
             &device,
             cache,
             rgraph,
             vk::BufferUsageFlags::STORAGE_BUFFER,
-            &self.emitters,
+            &self.meshes,
         ));
+        self.build_mesh_bounds();
+        self.emitters_changed = true;
+        self.upload_instances(device, cache, rgraph);
         self.material_data = Some(Array::from_slice_staging(
             &device,
             cache,
@@ -136,6 +459,11 @@ impl Scene {
 
         self.textures_gpu = Some(vec![]);
         for texture in self.textures.iter() {
+            if let image::DynamicImage::ImageRgba32F(img) = texture {
+                let img = Self::upload_hdr_texture(device, cache, rgraph, img);
+                self.textures_gpu.as_mut().unwrap().push(img);
+                continue;
+            }
             let mut img_loader = ImageLoader::new(device).unwrap();
             let img = texture.as_rgba8().unwrap();
             let img = img_loader
@@ -151,24 +479,218 @@ impl Scene {
             self.textures_gpu.as_mut().unwrap().push(img);
         }
     }
+    ///
+    /// Uploads the instances and, if emitters changed, everything derived from them. The emitter
+    /// distributions depend on the world space area and bounds of area emitters only, the scene
+    /// radius scales the power of all infinite emitters alike.
+    ///
+    fn upload_instances(
+        &mut self,
+        device: &Arc<Device>,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
+    ) {
+        self.instance_data = Some(Array::from_slice_staging(
+            &device,
+            cache,
+            rgraph,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            // Bind a dummy entry when all instances have been removed.
+            if self.instances.is_empty() {
+                &[common::Instance::default()]
+            } else {
+                &self.instances
+            },
+        ));
+        if !self.emitters_changed {
+            return;
+        }
+        self.emitters_changed = false;
+        self.build_triangle_cdfs();
+        self.triangle_cdf_data = Some(Array::from_slice_staging(
+            &device,
+            cache,
+            rgraph,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            if self.triangle_cdf.is_empty() {
+                &[0.]
+            } else {
+                &self.triangle_cdf
+            },
+        ));
+        self.emitter_data = Some(Array::from_slice_staging(
+            &device,
+            cache,
+            rgraph,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            if self.emitters.is_empty() {
+                &[Emitter::default()]
+            } else {
+                &self.emitters
+            },
+        ));
+        let light_bvh = LightBvh::new(&self.emitter_bounds());
+        let emitter_alias = self.emitter_alias(&light_bvh);
+        // Bind a dummy entry when there are no emitters.
+        self.emitter_alias_data = Some(Array::from_slice_staging(
+            &device,
+            cache,
+            rgraph,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            if emitter_alias.entries.is_empty() {
+                &[AliasEntry::default()]
+            } else {
+                &emitter_alias.entries
+            },
+        ));
+        self.light_bvh_data = Some(Array::from_slice_staging(
+            &device,
+            cache,
+            rgraph,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            if light_bvh.is_empty() {
+                &[LightBvhNode::default()]
+            } else {
+                &light_bvh.nodes
+            },
+        ));
+        self.light_bvh_trail_data = Some(Array::from_slice_staging(
+            &device,
+            cache,
+            rgraph,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            if light_bvh.bit_trails.is_empty() {
+                &[0]
+            } else {
+                &light_bvh.bit_trails
+            },
+        ));
+    }
+    ///
+    /// Uploads a HDR image as RGBA32F, ImageLoader only handles 8 bit formats.
+    ///
+    fn upload_hdr_texture(
+        device: &Arc<Device>,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
+        img: &image::Rgba32FImage,
+    ) -> Arc<Image> {
+        let data: &[u8] = bytemuck::cast_slice(img.as_raw());
+        let mut staging_buf = cache
+            .lease(BufferInfo::new_mappable(
+                data.len() as _,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+            ))
+            .unwrap();
+        Buffer::mapped_slice_mut(staging_buf.as_mut())[..data.len()].copy_from_slice(data);
+
+        let texture = Arc::new(
+            Image::create(
+                device,
+                ImageInfo::new_2d(
+                    vk::Format::R32G32B32A32_SFLOAT,
+                    img.width(),
+                    img.height(),
+                    vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+                ),
+            )
+            .unwrap(),
+        );
+
+        let staging_node = rgraph.bind_node(staging_buf);
+        let texture_node = rgraph.bind_node(&texture);
+        rgraph.copy_buffer_to_image(staging_node, texture_node);
+        texture
+    }
     pub fn update(&mut self, device: &Arc<Device>, cache: &mut HashPool, rgraph: &mut RenderGraph) {
         // Upload to gpu
         self.upload(device, cache, rgraph);
-        // Create blases
-        for instance in self.instances.iter() {
-            let mesh = &self.meshes[instance.mesh as usize];
-            self.blases.push(Blas::create(
-                &device,
-                self.index_data.as_ref().unwrap(),
-                mesh.indices as usize,
-                mesh.indices_count as usize / 3,
-                self.position_data.as_ref().unwrap(),
-                mesh.positions as usize,
-            ))
+        self.instances_changed = false;
+        // Pose the deformed meshes before their blases are built.
+        self.deform_pass = (!self.deformations.is_empty())
+            .then(|| DeformPass::new(device, cache, rgraph, &self.deformations));
+        self.deform(device, cache, rgraph);
+
+        // Create one blas per mesh, shared by all instances of that mesh. Static geometry is
+        // compacted once built (see `compact_blases`), deformed geometry is refit every time its
+        // pose changes.
+        self.blases = self
+            .meshes
+            .iter()
+            .enumerate()
+            .map(|(i, mesh)| {
+                Blas::create(
+                    &device,
+                    self.index_data.as_ref().unwrap(),
+                    mesh.indices as usize,
+                    mesh.indices_count as usize / 3,
+                    self.position_data.as_ref().unwrap(),
+                    mesh.positions as usize,
+                    if self.deformations.is_deformed(i) {
+                        vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD
+                            | vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE
+                    } else {
+                        vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+                            | vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION
+                    },
+                )
+            })
+            .collect();
+        for blas in self.blases.iter() {
+            blas.build(cache, rgraph);
+        }
+        let static_blases = self
+            .static_blases()
+            .map(|(_, blas)| blas)
+            .collect::<Vec<_>>();
+        let compaction = (!static_blases.is_empty())
+            .then(|| CompactionQuery::new(device, rgraph, &static_blases));
+        self.compaction = compaction;
+
+        self.build_tlas(device, cache, rgraph);
+    }
+    ///
+    /// Replaces the blases by their compacted copies and rebuilds the tlas referencing them.
+    /// Has to be called after the graph passed to `update` has been submitted, does nothing
+    /// until the compacted sizes are known so it can be called every frame without stalling.
+    /// Returns the blas memory before and after compaction in bytes once compacted.
+    ///
+    pub fn compact_blases(
+        &mut self,
+        device: &Arc<Device>,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
+    ) -> Option<(u64, u64)> {
+        let sizes = self.compaction.as_ref()?.sizes()?;
+        self.compaction = None;
+        let before = self.blases.iter().map(|blas| blas.size()).sum();
+        let static_blases = self.static_blases().map(|(i, _)| i).collect::<Vec<_>>();
+        for (i, size) in static_blases.into_iter().zip(sizes) {
+            let blas = &mut self.blases[i];
+            if size > 0 && size < blas.size() {
+                blas.compact(rgraph, size);
+            }
         }
+        let after = self.blases.iter().map(|blas| blas.size()).sum();
+
+        self.build_tlas(device, cache, rgraph);
+        Some((before, after))
+    }
+    ///
+    /// Blases of meshes that are not deformed, with the index of their mesh.
+    ///
+    fn static_blases(&self) -> impl Iterator<Item = (usize, &Blas<Vec3>)> {
+        self.blases
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !self.deformations.is_deformed(*i))
+    }
+    ///
+    /// Instances of the tlas, every instance references the blas of its mesh.
+    ///
+    fn tlas_instances(&self) -> Vec<vk::AccelerationStructureInstanceKHR> {
         // Transform instances into AccelerationStructureInstanceKHR types
-        let instances = self
-            .instances
+        self.instances
             .iter()
             .enumerate()
             .map(|(i, instance)| vk::AccelerationStructureInstanceKHR {
@@ -194,27 +716,176 @@ impl Scene {
                     vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as _,
                 ),
                 acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
-                    device_handle: AccelerationStructure::device_address(&self.blases[i].accel),
+                    device_handle: AccelerationStructure::device_address(
+                        &self.blases[instance.mesh as usize].accel,
+                    ),
                 },
             })
-            .collect::<Vec<_>>();
-
-        // Create tlas from instances
-        self.tlas = Tlas::create(&device, &instances);
-
-        // Build blas and tlas
-        let blas_nodes = self
-            .blases
+            .collect()
+    }
+    fn blas_nodes(&self, rgraph: &mut RenderGraph) -> Vec<AnyAccelerationStructureNode> {
+        self.blases
             .iter()
             .map(|blas| {
-                blas.build(cache, rgraph);
                 AnyAccelerationStructureNode::AccelerationStructure(rgraph.bind_node(&blas.accel))
             })
-            .collect::<Vec<_>>();
-        self.tlas
-            .as_ref()
-            .unwrap()
-            .build(cache, rgraph, &blas_nodes);
+            .collect()
+    }
+    fn build_tlas(&mut self, device: &Arc<Device>, cache: &mut HashPool, rgraph: &mut RenderGraph) {
+        // Create tlas from instances, updatable to move instances later on. Empty if there are
+        // no instances, so that instances can be added to an empty scene.
+        let tlas = Tlas::create(
+            &device,
+            &self.tlas_instances(),
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+                | vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE,
+        );
+
+        let blas_nodes = self.blas_nodes(rgraph);
+        tlas.build(cache, rgraph, &blas_nodes);
+        self.tlas = Some(tlas);
+    }
+
+    ///
+    /// Evaluates `clip` of all animations at `time` (in seconds), moves the animated instances
+    /// and cameras and poses the deformed meshes. Returns whether the scene has any animation.
+    ///
+    pub fn animate(&mut self, clip: usize, time: f32) -> bool {
+        let animations = std::mem::take(&mut self.animations);
+        for animation in animations.iter() {
+            animation.apply(clip, time, self);
+        }
+        self.animations = animations;
+        !self.animations.is_empty()
+    }
+    ///
+    /// Sets the joint matrices and morph target weights of a deformer, takes effect with the
+    /// next `update_deformation`.
+    ///
+    pub fn set_deformation(&mut self, deformer: usize, joints: &[Mat4], weights: &[f32]) {
+        self.deformations.set_pose(deformer, joints, weights);
+        self.deformation_changed = true;
+    }
+    ///
+    /// Writes the posed vertices of all deformed meshes into the vertex buffers.
+    ///
+    fn deform(&mut self, device: &Arc<Device>, cache: &mut HashPool, rgraph: &mut RenderGraph) {
+        self.deformation_changed = false;
+        if let Some(deform_pass) = self.deform_pass.as_ref() {
+            deform_pass.record(
+                device,
+                cache,
+                rgraph,
+                &self.deformations,
+                self.position_data.as_ref().unwrap(),
+                self.normal_data.as_ref().unwrap(),
+                self.tangent_data.as_ref().unwrap(),
+            );
+        }
+    }
+    ///
+    /// Deforms the meshes whose pose changed and refits their blases, the tlas is refit with the
+    /// next `update_instances`. The emitter distributions keep using the rest pose.
+    /// Returns whether anything changed.
+    ///
+    pub fn update_deformation(
+        &mut self,
+        device: &Arc<Device>,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
+    ) -> bool {
+        if !self.deformation_changed || self.deform_pass.is_none() || self.blases.is_empty() {
+            return false;
+        }
+        self.deform(device, cache, rgraph);
+        for (i, blas) in self.blases.iter().enumerate() {
+            if self.deformations.is_deformed(i) {
+                blas.update(cache, rgraph);
+            }
+        }
+        // The bounds of the deformed blases changed.
+        self.instances_changed = true;
+        true
+    }
+    ///
+    /// Moves an instance, takes effect with the next `update_instances`.
+    ///
+    pub fn set_transform(&mut self, instance: usize, to_world: Mat4) {
+        self.instances[instance].to_world = to_world;
+        self.instances_changed = true;
+        self.emitters_changed |= self.instances[instance].emitter >= 0;
+    }
+    ///
+    /// Adds an instance of a mesh and returns its index, an area emitter is added for emissive
+    /// materials. Takes effect with the next `update_instances`.
+    ///
+    pub fn add_instance(&mut self, mesh: usize, material: usize, to_world: Mat4) -> usize {
+        let instance = self.instances.len();
+        let mut emitter = -1;
+        if self.materials[material].emission_factor != Vec3::ZERO {
+            emitter = self.emitters.len() as i32;
+            self.emitters.push(Emitter::area(instance as u32));
+        }
+        self.instances.push(common::Instance {
+            to_world,
+            mesh: mesh as u32,
+            material: material as u32,
+            emitter,
+        });
+        self.instances_changed = true;
+        self.emitters_changed = true;
+        instance
+    }
+    ///
+    /// Removes an instance together with its area emitter. The last instance (and emitter) takes
+    /// the index of the removed one, which invalidates indices of it stored elsewhere, e.g. in
+    /// `AnimationNode::instances`. Takes effect with the next `update_instances`.
+    ///
+    pub fn remove_instance(&mut self, instance: usize) -> common::Instance {
+        let removed = self.instances.swap_remove(instance);
+        if let Some(moved) = self.instances.get(instance) {
+            if moved.emitter >= 0 {
+                self.emitters[moved.emitter as usize].instance = instance as u32;
+            }
+        }
+        if removed.emitter >= 0 {
+            let emitter = removed.emitter as usize;
+            self.emitters.swap_remove(emitter);
+            if let Some(moved) = self.emitters.get(emitter) {
+                if moved.ty == Emitter::TY_AREA {
+                    self.instances[moved.instance as usize].emitter = emitter as i32;
+                }
+            }
+        }
+        self.instances_changed = true;
+        self.emitters_changed = true;
+        removed
+    }
+    ///
+    /// Uploads changed instances and refits the tlas, or rebuilds it if instances were added or
+    /// removed. Returns whether anything changed.
+    ///
+    pub fn update_instances(
+        &mut self,
+        device: &Arc<Device>,
+        cache: &mut HashPool,
+        rgraph: &mut RenderGraph,
+    ) -> bool {
+        if !self.instances_changed {
+            return false;
+        }
+        self.instances_changed = false;
+        self.upload_instances(device, cache, rgraph);
+
+        let instances = self.tlas_instances();
+        let blas_nodes = self.blas_nodes(rgraph);
+        match self.tlas.as_mut() {
+            Some(tlas) if tlas.instance_count() == instances.len() => {
+                tlas.update(cache, rgraph, &instances, &blas_nodes);
+            }
+            _ => self.build_tlas(device, cache, rgraph),
+        }
+        true
     }
 
     pub fn bind(&self, rgraph: &mut RenderGraph) -> SceneBinding {
@@ -223,11 +894,17 @@ impl Scene {
             indices: rgraph.bind_node(&self.index_data.as_ref().unwrap().buf),
             positions: rgraph.bind_node(&self.position_data.as_ref().unwrap().buf),
             normals: rgraph.bind_node(&self.normal_data.as_ref().unwrap().buf),
+            tangents: rgraph.bind_node(&self.tangent_data.as_ref().unwrap().buf),
             uvs: rgraph.bind_node(&self.uv_data.as_ref().unwrap().buf),
+            env_cdf: rgraph.bind_node(&self.env_cdf_data.as_ref().unwrap().buf),
 
             instances: rgraph.bind_node(&self.instance_data.as_ref().unwrap().buf),
             meshes: rgraph.bind_node(&self.mesh_data.as_ref().unwrap().buf),
             emitters: rgraph.bind_node(&self.emitter_data.as_ref().unwrap().buf),
+            emitter_alias: rgraph.bind_node(&self.emitter_alias_data.as_ref().unwrap().buf),
+            light_bvh: rgraph.bind_node(&self.light_bvh_data.as_ref().unwrap().buf),
+            triangle_cdf: rgraph.bind_node(&self.triangle_cdf_data.as_ref().unwrap().buf),
+            light_bvh_trails: rgraph.bind_node(&self.light_bvh_trail_data.as_ref().unwrap().buf),
             materials: rgraph.bind_node(&self.material_data.as_ref().unwrap().buf),
             cameras: rgraph.bind_node(&self.camera_data.as_ref().unwrap().buf),
 
@@ -247,10 +924,16 @@ pub struct SceneBinding {
     pub indices: BufferNode,
     pub positions: BufferNode,
     pub normals: BufferNode,
+    pub tangents: BufferNode,
     pub uvs: BufferNode,
+    pub env_cdf: BufferNode,
     pub instances: BufferNode,
     pub meshes: BufferNode,
     pub emitters: BufferNode,
+    pub emitter_alias: BufferNode,
+    pub light_bvh: BufferNode,
+    pub light_bvh_trails: BufferNode,
+    pub triangle_cdf: BufferNode,
     pub materials: BufferNode,
     pub cameras: BufferNode,
 
@@ -0,0 +1,88 @@
+use crate::scene::Film;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Parser, ValueEnum};
+use std::path::PathBuf;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RendererType {
+    /// Unidirectional path tracer with next event estimation.
+    Pt,
+    /// ReSTIR GI.
+    Restir,
+}
+
+///
+/// Settings shared between the renderers, post processing and the headless driver.
+///
+#[derive(Parser, Debug, Clone)]
+#[command(about = "Vulkan path tracer")]
+pub struct Settings {
+    /// Scene files (.gltf, .glb, .obj, Mitsuba .xml) to load, appended in order. An .hdr or .exr image is used as
+    /// environment map.
+    #[arg(default_value = "assets/cornell-box.gltf")]
+    pub scenes: Vec<PathBuf>,
+
+    /// Renderer used to render the scene.
+    #[arg(short, long, value_enum, default_value_t = RendererType::Pt)]
+    pub renderer: RendererType,
+
+    #[arg(long, default_value_t = 1024)]
+    pub width: u32,
+    #[arg(long, default_value_t = 1024)]
+    pub height: u32,
+
+    /// Samples per pixel to accumulate. Without it the window keeps accumulating indefinitely
+    /// and headless rendering uses 256 samples.
+    #[arg(long)]
+    pub spp: Option<u32>,
+
+    #[arg(long, default_value_t = 8)]
+    pub max_depth: u32,
+    /// Depth after which russian roulette is used to terminate paths.
+    #[arg(long, default_value_t = 2)]
+    pub rr_depth: u32,
+
+    #[arg(short, long, default_value_t = 0)]
+    pub camera: u32,
+
+    /// Exposure in stops, applied before the sRGB conversion.
+    #[arg(long, default_value_t = 0.)]
+    pub exposure: f32,
+
+    /// Render headless and write the image to this path (as .exr and .png).
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 0)]
+    pub seed: u32,
+
+    /// Animation time in seconds at which the scene is rendered.
+    #[arg(long, default_value_t = 0.)]
+    pub time: f32,
+    /// Advance the animation time every frame.
+    #[arg(long)]
+    pub play: bool,
+    /// Index of the animation clip played in every file, files without it stay in their rest
+    /// pose.
+    #[arg(long, default_value_t = 0)]
+    pub clip: usize,
+}
+
+impl Settings {
+    ///
+    /// Takes the image size and sample count from the film of the scene, unless they were given
+    /// on the command line.
+    ///
+    pub fn apply_film(&mut self, film: &Film, matches: &ArgMatches) {
+        let is_default = |id| matches.value_source(id) != Some(ValueSource::CommandLine);
+        if is_default("width") {
+            self.width = film.width;
+        }
+        if is_default("height") {
+            self.height = film.height;
+        }
+        if self.spp.is_none() {
+            self.spp = film.spp;
+        }
+    }
+}
@@ -0,0 +1,134 @@
+#ifndef DIELECTRIC_BSDF_GLSL
+#define DIELECTRIC_BSDF_GLSL
+
+#include "interaction.glsl"
+#include "bsdf/microfacet.glsl"
+
+// Smooth and rough (GGX, Walter et al. 2007) dielectric.
+// wi may lie on either side of the surface, ior is the relative index of refraction of the
+// inside (against the normal) to the outside.
+// Below this alpha the interface is treated as perfectly smooth.
+#define DIELECTRIC_SMOOTH_ALPHA 1e-3
+
+// Exact unpolarized Fresnel reflectance.
+// cos_theta_i is measured on the incident side and eta = eta_t / eta_i.
+float fresnel_dielectric(float cos_theta_i, float eta){
+    float sin2_theta_t = (1. - cos_theta_i * cos_theta_i) / (eta * eta);
+    if (sin2_theta_t >= 1.){
+        // Total internal reflection
+        return 1.;
+    }
+    float cos_theta_t = sqrt(1. - sin2_theta_t);
+
+    float r_s = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    float r_p = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    return 0.5 * (r_s * r_s + r_p * r_p);
+}
+
+// Evaluate f(wi, wo) * |cos_theta_o| of the rough dielectric.
+// Returns 0 for smooth interfaces since those only have delta components.
+vec3 dielectric_eval(float alpha, float ior, vec3 wi, vec3 wo){
+    if (alpha <= DIELECTRIC_SMOOTH_ALPHA || cos_theta(wi) == 0. || cos_theta(wo) == 0.){
+        return vec3(0.);
+    }
+    // Flip into the frame where wi lies in the upper hemisphere.
+    float s = sign(cos_theta(wi));
+    wi *= s;
+    wo *= s;
+    float eta = s > 0.? ior : 1. / ior;
+
+    bool reflected = cos_theta(wo) > 0.;
+
+    vec3 h = normalize(reflected? wi + wo : wi + eta * wo);
+    h *= sign(h.z);
+
+    float wi_h = dot(wi, h);
+    float wo_h = dot(wo, h);
+    // Back-facing microfacets
+    if (wi_h <= 0. || (reflected? wo_h <= 0. : wo_h >= 0.)){
+        return vec3(0.);
+    }
+
+    float D = ggx_D(h, alpha);
+    float G = ggx_G(wi, wo, alpha);
+    float F = fresnel_dielectric(wi_h, eta);
+
+    if (reflected){
+        return vec3(F * D * G / (4. * cos_theta(wi)));
+    }else{
+        float denom = wi_h + eta * wo_h;
+        return vec3((1. - F) * D * G * abs(wi_h * wo_h) / (cos_theta(wi) * denom * denom));
+    }
+}
+
+float dielectric_pdf(float alpha, float ior, vec3 wi, vec3 wo){
+    if (alpha <= DIELECTRIC_SMOOTH_ALPHA || cos_theta(wi) == 0. || cos_theta(wo) == 0.){
+        return 0.;
+    }
+    float s = sign(cos_theta(wi));
+    wi *= s;
+    wo *= s;
+    float eta = s > 0.? ior : 1. / ior;
+
+    bool reflected = cos_theta(wo) > 0.;
+
+    vec3 h = normalize(reflected? wi + wo : wi + eta * wo);
+    h *= sign(h.z);
+
+    float wi_h = dot(wi, h);
+    float wo_h = dot(wo, h);
+    if (wi_h <= 0. || (reflected? wo_h <= 0. : wo_h >= 0.)){
+        return 0.;
+    }
+
+    float F = fresnel_dielectric(wi_h, eta);
+    float pdf_h = ggx_vndf_pdf(wi, h, alpha);
+
+    if (reflected){
+        return F * pdf_h / (4. * wi_h);
+    }else{
+        float denom = wi_h + eta * wo_h;
+        return (1. - F) * pdf_h * eta * eta * abs(wo_h) / (denom * denom);
+    }
+}
+
+// Samples reflection or refraction proportional to the Fresnel term.
+// value is f(wi, wo) * |cos_theta_o| / pdf.
+void dielectric_sample(
+    float alpha,
+    float ior,
+    vec3 wi,
+    float sample1,
+    vec2 sample2,
+    out BSDFSample bs,
+    out vec3 value){
+
+    float s = sign(cos_theta(wi));
+    wi *= s;
+    float eta = s > 0.? ior : 1. / ior;
+
+    bool smooth_interface = alpha <= DIELECTRIC_SMOOTH_ALPHA;
+    vec3 h = smooth_interface? vec3(0., 0., 1.) : sample_ggx_vndf(wi, alpha, sample2);
+
+    float F = fresnel_dielectric(dot(wi, h), eta);
+
+    bool reflected = sample1 < F;
+    if (reflected){
+        bs.wo = reflect(-wi, h);
+    }else{
+        bs.wo = refract(-wi, h, 1. / eta);
+    }
+    bs.wo *= s;
+    bs.delta = smooth_interface;
+
+    if (smooth_interface){
+        bs.pdf = reflected? F : 1. - F;
+        // Radiance is compressed when entering the denser medium.
+        value = reflected? vec3(1.) : vec3(1. / (eta * eta));
+    }else{
+        bs.pdf = dielectric_pdf(alpha, ior, wi * s, bs.wo);
+        value = bs.pdf > 0.? dielectric_eval(alpha, ior, wi * s, bs.wo) / bs.pdf : vec3(0.);
+    }
+}
+
+#endif //DIELECTRIC_BSDF_GLSL
@@ -0,0 +1,61 @@
+#ifndef MICROFACET_GLSL
+#define MICROFACET_GLSL
+
+#include "math.glsl"
+
+// GGX (Trowbridge-Reitz) microfacet distribution in the local shading frame.
+
+vec3 fresnel_schlick(vec3 F0, float cos_theta){
+    return F0 + (vec3(1.) - F0) * pow(clamp(1. - cos_theta, 0., 1.), 5.);
+}
+
+float ggx_D(vec3 h, float alpha){
+    if (h.z <= 0.){
+        return 0.;
+    }
+    float a2 = alpha * alpha;
+    float d = h.z * h.z * (a2 - 1.) + 1.;
+    return a2 / (PI * d * d);
+}
+
+// Smith masking function for a single direction.
+float ggx_G1(vec3 v, float alpha){
+    float cos_theta = abs(v.z);
+    float a2 = alpha * alpha;
+    return 2. * cos_theta / (cos_theta + sqrt(a2 + (1. - a2) * cos_theta * cos_theta));
+}
+
+// Separable Smith masking-shadowing.
+float ggx_G(vec3 wi, vec3 wo, float alpha){
+    return ggx_G1(wi, alpha) * ggx_G1(wo, alpha);
+}
+
+// Sample the distribution of visible normals (Heitz 2018).
+vec3 sample_ggx_vndf(vec3 wi, float alpha, vec2 sample1){
+    vec3 v = normalize(vec3(alpha * wi.x, alpha * wi.y, wi.z));
+
+    float lensq = v.x * v.x + v.y * v.y;
+    vec3 t1 = lensq > 0.? vec3(-v.y, v.x, 0.) * inversesqrt(lensq) : vec3(1., 0., 0.);
+    vec3 t2 = cross(v, t1);
+
+    float r = sqrt(sample1.x);
+    float phi = 2. * PI * sample1.y;
+    float p1 = r * cos(phi);
+    float p2 = r * sin(phi);
+    float s = 0.5 * (1. + v.z);
+    p2 = (1. - s) * sqrt(max(0., 1. - p1 * p1)) + s * p2;
+
+    vec3 n = p1 * t1 + p2 * t2 + sqrt(max(0., 1. - p1 * p1 - p2 * p2)) * v;
+
+    return normalize(vec3(alpha * n.x, alpha * n.y, max(0., n.z)));
+}
+
+// Probability density of sampling the half vector h with sample_ggx_vndf.
+float ggx_vndf_pdf(vec3 wi, vec3 h, float alpha){
+    if (wi.z <= 0.){
+        return 0.;
+    }
+    return ggx_G1(wi, alpha) * max(0., dot(wi, h)) * ggx_D(h, alpha) / wi.z;
+}
+
+#endif //MICROFACET_GLSL
@@ -0,0 +1,172 @@
+#ifndef PRINCIPLED_BSDF_GLSL
+#define PRINCIPLED_BSDF_GLSL
+
+#include "interaction.glsl"
+#include "warp.glsl"
+#include "texture.glsl"
+#include "spectrum.glsl"
+#include "bsdf/microfacet.glsl"
+#include "bsdf/dielectric.glsl"
+
+// glTF metallic-roughness material.
+// Mixes a lambertian diffuse lobe with a GGX specular lobe, the Fresnel-Schlick term blends
+// between a dielectric (F0 = 0.04) and a conductor (F0 = base_color) depending on metallic.
+// The opaque part (principled_*) is mirrored in src/bsdf.rs.
+// KHR_materials_transmission blends in a rough dielectric for the non-metallic part.
+
+struct PrincipledParams{
+    vec3 base_color;
+    float metallic;
+    float alpha;
+    float transmission;
+    float ior;
+};
+
+PrincipledParams principled_params(in SurfaceInteraction si){
+    PrincipledParams p;
+    p.base_color = eval_texture(si.material.base_color, si.uv);
+    // Same channels as glTF: roughness in g, metallic in b.
+    vec3 metallic_roughness = eval_texture(si.material.metallic_roughness, si.uv);
+    p.metallic = clamp(metallic_roughness.b, 0., 1.);
+    p.alpha = max(metallic_roughness.g * metallic_roughness.g, 1e-3);
+    p.transmission = eval_texture(si.material.transmission, si.uv).r;
+    p.ior = si.material.ior;
+    return p;
+}
+
+// Probability of choosing the specular lobe.
+float principled_specular_prob(in PrincipledParams p, float cos_theta_i){
+    vec3 F0 = mix(vec3(0.04), p.base_color, p.metallic);
+    float spec = luminance(fresnel_schlick(F0, cos_theta_i));
+    float diff = luminance(p.base_color) * (1. - p.metallic);
+    if (spec + diff <= 0.){
+        return 0.5;
+    }
+    return spec / (spec + diff);
+}
+
+vec3 principled_eval(in PrincipledParams p, vec3 wi, vec3 wo){
+    float cos_theta_i = cos_theta(wi);
+    float cos_theta_o = cos_theta(wo);
+    if (cos_theta_i <= 0. || cos_theta_o <= 0.){
+        return vec3(0.);
+    }
+
+    vec3 h = normalize(wi + wo);
+    vec3 F0 = mix(vec3(0.04), p.base_color, p.metallic);
+    vec3 F = fresnel_schlick(F0, dot(wi, h));
+
+    float D = ggx_D(h, p.alpha);
+    float G = ggx_G(wi, wo, p.alpha);
+
+    vec3 specular = F * D * G / (4. * cos_theta_i * cos_theta_o);
+    // Light reflected by the specular layer at either direction does not reach the diffuse base,
+    // weighting with the half vector instead gains energy at grazing angles.
+    vec3 diffuse = (1. - p.metallic)
+        * (vec3(1.) - fresnel_schlick(vec3(0.04), cos_theta_i))
+        * (vec3(1.) - fresnel_schlick(vec3(0.04), cos_theta_o))
+        * p.base_color / PI;
+
+    return (diffuse + specular) * cos_theta_o;
+}
+
+float principled_pdf(in PrincipledParams p, vec3 wi, vec3 wo){
+    float cos_theta_i = cos_theta(wi);
+    float cos_theta_o = cos_theta(wo);
+    if (cos_theta_i <= 0. || cos_theta_o <= 0.){
+        return 0.;
+    }
+
+    float p_spec = principled_specular_prob(p, cos_theta_i);
+
+    vec3 h = normalize(wi + wo);
+    float pdf_spec = ggx_vndf_pdf(wi, h, p.alpha) / (4. * dot(wi, h));
+    float pdf_diff = square_to_cosine_hemisphere_pdf(wo);
+
+    return p_spec * pdf_spec + (1. - p_spec) * pdf_diff;
+}
+
+// Weight of the dielectric (transmissive) part.
+float transmission_weight(in PrincipledParams p){
+    return clamp(p.transmission * (1. - p.metallic), 0., 1.);
+}
+
+// Evaluate f(wi, wo) * |cos_theta_o| for the opaque and transmissive parts combined.
+vec3 principled_transmission_eval(in PrincipledParams p, vec3 wi, vec3 wo){
+    float w_t = transmission_weight(p);
+    return (1. - w_t) * principled_eval(p, wi, wo) + w_t * dielectric_eval(p.alpha, p.ior, wi, wo);
+}
+
+float principled_transmission_pdf(in PrincipledParams p, vec3 wi, vec3 wo){
+    float w_t = transmission_weight(p);
+    return (1. - w_t) * principled_pdf(p, wi, wo) + w_t * dielectric_pdf(p.alpha, p.ior, wi, wo);
+}
+
+// Sample an outgoing direction wo and evaluate the bsdf for that direction.
+//
+// value: The BSDF value f(wi, wo) divided by the probability p(wo)
+//        (multiplied by the cosinus foreshortening term cos_theta_o for non-delta components).
+//
+
+void sample_bsdf(
+    in SurfaceInteraction si,
+    in float sample1,
+    in vec2 sample2,
+    out BSDFSample bs,
+    out vec3 value){
+
+    PrincipledParams p = principled_params(si);
+    float w_t = transmission_weight(p);
+
+    bs.delta = false;
+
+    if (sample1 < w_t){
+        sample1 /= w_t;
+        dielectric_sample(p.alpha, p.ior, si.wi, sample1, sample2, bs, value);
+        if (bs.delta){
+            bs.pdf *= w_t;
+            return;
+        }
+    }else{
+        sample1 = (sample1 - w_t) / (1. - w_t);
+
+        float cos_theta_i = cos_theta(si.wi);
+        if (cos_theta_i <= 0.){
+            bs.wo = vec3(0., 0., 1.);
+            bs.pdf = 0.;
+            value = vec3(0.);
+            return;
+        }
+
+        if (sample1 < principled_specular_prob(p, cos_theta_i)){
+            vec3 h = sample_ggx_vndf(si.wi, p.alpha, sample2);
+            bs.wo = reflect(-si.wi, h);
+        }else{
+            bs.wo = square_to_cosine_hemisphere(sample2);
+        }
+    }
+
+    bs.pdf = principled_transmission_pdf(p, si.wi, bs.wo);
+    value = bs.pdf > 0.? principled_transmission_eval(p, si.wi, bs.wo) / bs.pdf : vec3(0.);
+}
+
+
+// Evaluate the bsdf including the cosinus foreshortening term.
+// f(wi, wo) * cos_theta_o
+vec3 eval_bsdf(in SurfaceInteraction si, in vec3 wo){
+    return principled_transmission_eval(principled_params(si), si.wi, wo);
+}
+
+// Calculate the probability of sampling a direction wo when using the function sample_bsdf.
+float bsdf_pdf(in SurfaceInteraction si, in vec3 wo){
+    return principled_transmission_pdf(principled_params(si), si.wi, wo);
+}
+
+// Combine eval and pdf
+void bsdf_eval_pdf(in SurfaceInteraction si, in vec3 wo, out vec3 value, out float pdf){
+    PrincipledParams p = principled_params(si);
+    value = principled_transmission_eval(p, si.wi, wo);
+    pdf = principled_transmission_pdf(p, si.wi, wo);
+}
+
+#endif //PRINCIPLED_BSDF_GLSL
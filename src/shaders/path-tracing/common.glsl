@@ -16,6 +16,7 @@ struct Mesh{
     uint indices_count;
     uint positions;
     uint normals;
+    uint tangents;
     uint uvs;
 };
 struct Instance{
@@ -28,21 +29,51 @@ struct Emitter{
     Texture emission;
     uint instance;
     uint ty;
+    vec3 position;
+    vec3 direction;
+    float cos_inner_cone;
+    float cos_outer_cone;
+    uint triangle_cdf;
+    float area;
 };
 #define EMITTER_TY_NONE 0
 #define EMITTER_TY_ENV 1
 #define EMITTER_TY_AREA 2
+#define EMITTER_TY_POINT 3
+#define EMITTER_TY_SPOT 4
+#define EMITTER_TY_DIRECTIONAL 5
+struct AliasEntry{
+    float threshold;
+    uint alias;
+    float pdf;
+};
+struct LightBvhNode{
+    vec3 min;
+    vec3 max;
+    vec3 axis;
+    float cos_theta_o;
+    float cos_theta_e;
+    float power;
+    uint index;
+    uint leaf;
+    uint two_sided;
+};
 struct Material{
     Texture normal;
     Texture base_color;
     Texture metallic_roughness;
     Texture transmission;
+    float ior;
+    float normal_scale;
+    Texture emission;
+    vec3 emission_factor;
 };
 struct Camera{
     mat4 to_world;
     mat4 to_view;
     float near_clip;
     float far_clip;
+    float fov_y;
 };
 
 struct RestirSample{
@@ -81,6 +112,7 @@ struct Payload{
 struct BSDFSample{
     vec3 wo;
     float pdf;
+    bool delta;
 };
 
 
@@ -4,6 +4,108 @@
 #include "interaction.glsl"
 #include "records.glsl"
 #include "instance.glsl"
+#include "light-bvh.glsl"
+
+//===========================================================
+// Environment map:
+//===========================================================
+// Equirectangular mapping with +y up, v = 0 is the top row of the image.
+// Sampling mirrors Distribution2D in src/distribution.rs.
+
+bool has_env(){
+    return emitters.length() > 0 && emitters[0].ty == EMITTER_TY_ENV;
+}
+
+vec2 env_direction_to_uv(vec3 d){
+    float u = atan(d.x, -d.z) / (2. * PI) + 0.5;
+    float v = acos(clamp(d.y, -1., 1.)) / PI;
+    return vec2(u, v);
+}
+
+vec3 env_uv_to_direction(vec2 uv){
+    float phi = (uv.x - 0.5) * 2. * PI;
+    float theta = uv.y * PI;
+    return vec3(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+}
+
+// Index i of the segment with cdf[offset + i] <= u < cdf[offset + i + 1].
+uint env_cdf_find_segment(uint offset, uint count, float u){
+    uint first = 0;
+    uint last = count;
+    while (last - first > 1){
+        uint middle = (first + last) / 2;
+        if (env_cdf[offset + middle] <= u){
+            first = middle;
+        }else{
+            last = middle;
+        }
+    }
+    return first;
+}
+
+ivec2 env_texel(in Emitter emitter, vec2 uv){
+    ivec2 size = textureSize(textures[emitter.emission.texture], 0);
+    return clamp(ivec2(uv * vec2(size)), ivec2(0), size - 1);
+}
+
+// The environment map is piecewise constant, matching the sampled distribution.
+vec3 eval_env(in Emitter emitter, vec3 d){
+    return texelFetch(textures[emitter.emission.texture], env_texel(emitter, env_direction_to_uv(d)), 0).rgb;
+}
+
+// Probability density of sampling uv with respect to [0, 1]².
+float env_pdf_uv(in Emitter emitter, vec2 uv){
+    uvec2 size = uvec2(textureSize(textures[emitter.emission.texture], 0));
+    uvec2 texel = uvec2(env_texel(emitter, uv));
+    uint row = texel.y * (size.x + 1);
+    uint marginal = size.y * (size.x + 1);
+
+    float pdf_u = (env_cdf[row + texel.x + 1] - env_cdf[row + texel.x]) * float(size.x);
+    float pdf_v = (env_cdf[marginal + texel.y + 1] - env_cdf[marginal + texel.y]) * float(size.y);
+    return pdf_u * pdf_v;
+}
+
+vec2 sample_env_uv(in Emitter emitter, vec2 sample1, out float pdf){
+    uvec2 size = uvec2(textureSize(textures[emitter.emission.texture], 0));
+    uint marginal = size.y * (size.x + 1);
+
+    uint y = env_cdf_find_segment(marginal, size.y, sample1.y);
+    float cdf_v = env_cdf[marginal + y];
+    float width_v = env_cdf[marginal + y + 1] - cdf_v;
+    float v = (float(y) + (width_v > 0.? (sample1.y - cdf_v) / width_v : 0.)) / float(size.y);
+
+    uint row = y * (size.x + 1);
+    uint x = env_cdf_find_segment(row, size.x, sample1.x);
+    float cdf_u = env_cdf[row + x];
+    float width_u = env_cdf[row + x + 1] - cdf_u;
+    float u = (float(x) + (width_u > 0.? (sample1.x - cdf_u) / width_u : 0.)) / float(size.x);
+
+    pdf = width_u * float(size.x) * width_v * float(size.y);
+    return vec2(u, v);
+}
+
+// Converts a density over [0, 1]² into solid angle.
+float env_uv_pdf_to_solid_angle(float pdf_uv, vec2 uv){
+    float sin_theta = sin(uv.y * PI);
+    return sin_theta > 0.? pdf_uv / (2. * PI * PI * sin_theta) : 0.;
+}
+
+//===========================================================
+// Punctual lights:
+//===========================================================
+
+// Smooth falloff between the inner and outer cone as given by KHR_lights_punctual.
+// d points from the shading point to the light.
+float spot_attenuation(in Emitter emitter, vec3 d){
+    float cos_theta = dot(emitter.direction, -d);
+    float scale = 1. / max(0.001, emitter.cos_inner_cone - emitter.cos_outer_cone);
+    float attenuation = clamp((cos_theta - emitter.cos_outer_cone) * scale, 0., 1.);
+    return attenuation * attenuation;
+}
+
+//===========================================================
+// Emitters:
+//===========================================================
 
 void sample_direction(
     in Emitter emitter, 
@@ -11,10 +113,11 @@ void sample_direction(
     vec2 sample1, 
     out DirectionSample ds, 
     out vec3 val){
+    ds.delta = false;
     if (emitter.ty == EMITTER_TY_AREA){
         Instance instance = instances[emitter.instance];
         
-        PositionSample ps = sample_position(instance, sample1);
+        PositionSample ps = sample_position(emitter, instance, sample1);
         // //DEBUG:
         // imageStore(image[0], ivec2(gl_LaunchIDEXT.xy), vec4(ps.uv, 0., 1.));
         
@@ -30,29 +133,91 @@ void sample_direction(
 
         float dp = abs(dot(ds.d, ds.n));
         
-        ds.pdf = (dp > 0.)?dist2/dp:0.;
+        // Area to solid angle
+        ds.pdf = (dp > 0.)? ps.pdf * dist2/dp:0.;
+
+        Material material = materials[instance.material];
+        val = eval_emission(material, ds.uv);
+    } else if (emitter.ty == EMITTER_TY_ENV){
+        float pdf_uv;
+        vec2 uv = sample_env_uv(emitter, sample1, pdf_uv);
+
+        ds.d = env_uv_to_direction(uv);
+        ds.dist = 10000.;
+        ds.p = si.p + ds.d * ds.dist;
+        ds.n = -ds.d;
+        ds.uv = uv;
+        ds.pdf = env_uv_pdf_to_solid_angle(pdf_uv, uv);
 
-        //Material material = materials[instance.material];
-        val = eval_texture(emitter.emission, ds.uv);
+        val = eval_env(emitter, ds.d);
+    } else if (emitter.ty == EMITTER_TY_POINT || emitter.ty == EMITTER_TY_SPOT){
+        ds.p = emitter.position;
+        ds.d = ds.p - si.p;
+        float dist2 = dot(ds.d, ds.d);
+        ds.dist = sqrt(dist2);
+        ds.d /= ds.dist;
+        ds.n = -ds.d;
+        ds.pdf = 1.;
+        ds.delta = true;
+
+        val = emitter.emission.val / dist2;
+        if (emitter.ty == EMITTER_TY_SPOT){
+            val *= spot_attenuation(emitter, ds.d);
+        }
+    } else if (emitter.ty == EMITTER_TY_DIRECTIONAL){
+        ds.d = -emitter.direction;
+        ds.dist = 10000.;
+        ds.p = si.p + ds.d * ds.dist;
+        ds.n = emitter.direction;
+        ds.pdf = 1.;
+        ds.delta = true;
+
+        val = emitter.emission.val;
     } else{
         val = vec3(0.);
         ds.pdf = 0.;
     }
 }
 
-float pdf_emitter(uint emitter){
-    return 1. / float(emitters.length());
+bool is_infinite(in Emitter emitter){
+    return emitter.ty == EMITTER_TY_ENV || emitter.ty == EMITTER_TY_DIRECTIONAL;
+}
+
+// Probability of selecting the emitter for the shading point p.
+float pdf_emitter(uint emitter, vec3 p){
+    float pdf = emitter_alias[emitter].pdf;
+    if (!is_infinite(emitters[emitter])){
+        pdf *= pdf_light_bvh(emitter, p);
+    }
+    return pdf;
 }
 
-// void sample_emitter(float sample1, out uint emitter, out float weight, out float sample_reuse){
-//     float index_sample_scaled = sample1 * float(emitters.length());
-//     uint index = uint(index_sample_scaled);
-//     
-//     //emitter = emitters[index];
-//     emitter = index;
-//     weight = float(emitters.length());
-//     sample_reuse = index_sample_scaled - float(index);
-// }
+// The alias table selects either an infinite emitter (proportional to its power) or the light
+// BVH, which then selects one of the remaining emitters for the shading point p.
+// sample1 is rescaled for reuse.
+uint sample_emitter(vec3 p, inout float sample1, out float pdf){
+    uint idx = sample_reuse(sample1, emitters.length());
+    AliasEntry entry = emitter_alias[idx];
+    if (sample1 < entry.threshold){
+        sample1 = sample1 / entry.threshold;
+    }else{
+        sample1 = (sample1 - entry.threshold) / (1. - entry.threshold);
+        idx = entry.alias;
+    }
+    pdf = emitter_alias[idx].pdf;
+
+    if (is_infinite(emitters[idx])){
+        return idx;
+    }
+    uint emitter;
+    float pdf_bvh;
+    if (!sample_light_bvh(p, sample1, emitter, pdf_bvh)){
+        pdf = 0.;
+        return idx;
+    }
+    pdf *= pdf_bvh;
+    return emitter;
+}
 
 void sample_emitter_direction(
     in SurfaceInteraction si, 
@@ -60,15 +225,17 @@ void sample_emitter_direction(
     out DirectionSample ds, 
     out vec3 val){
     
-    float emitter_weight = 1. / float(emitters.length());
-    uint emitter_idx = sample_reuse(sample1.x, emitters.length());
+    float emitter_pdf;
+    uint emitter_idx = sample_emitter(si.p, sample1.x, emitter_pdf);
 
     Emitter emitter = emitters[emitter_idx];
     sample_direction(emitter, si, sample1, ds, val);
 
-    ds.pdf *= pdf_emitter(emitter_idx);
+    ds.pdf *= emitter_pdf;
     
-    val *= emitter_weight * abs(dot(ds.n, ds.d));
+    // Emitted radiance divided by the solid angle pdf.
+    // For punctual lights the pdf only accounts for the emitter selection.
+    val = ds.pdf > 0.? val / ds.pdf : vec3(0.);
 
     bool occluded = ray_test(spawn_ray_to(si, ds.p));
     if (occluded){
@@ -77,21 +244,33 @@ void sample_emitter_direction(
     }
 }
 
-float pdf_emitter_direction(in SurfaceInteraction si){
+// Probability of sampling the point on an emitter hit by a ray from p_ref.
+float pdf_emitter_direction(in SurfaceInteraction si, vec3 p_ref){
     Instance instance = instances[si.instance];
-    if (instance.emitter >= 0 && abs(cos_theta(si.wi)) > 0.){
-        Mesh mesh = meshes[instance.mesh];
-        
-        float pdf = (si.dist * si.dist) / abs(cos_theta(si.wi));
-        pdf *= pdf_emitter(instance.emitter);
-        
-        pdf *= square_to_uniform_triangle_pdf(si.barycentric.yz);
-        pdf *= 1. / si.area;
-        pdf *= 1. / float(mesh.indices_count / 3);
+    // Solid angle conversion uses the geometric normal, not the (normal mapped) shading frame.
+    float cos_theta_g = abs(dot(si.n, to_world(si, si.wi)));
+    if (instance.emitter >= 0 && cos_theta_g > 0.){
+        float pdf = (si.dist * si.dist) / cos_theta_g;
+        pdf *= pdf_emitter(instance.emitter, p_ref);
+        pdf *= sample_position_pdf(emitters[instance.emitter]);
         return pdf;
     }else{
         return 0;
     }
 }
 
+// Radiance arriving along a ray that left the scene in direction d.
+vec3 eval_env_emitter(vec3 d){
+    return has_env()? eval_env(emitters[0], d) : vec3(0.);
+}
+
+float pdf_env_direction(vec3 d){
+    if (!has_env()){
+        return 0.;
+    }
+    vec2 uv = env_direction_to_uv(d);
+    // Infinite emitters do not depend on the shading point.
+    return env_uv_pdf_to_solid_angle(env_pdf_uv(emitters[0], uv), uv) * pdf_emitter(0, vec3(0.));
+}
+
 #endif //EMITTER_GLSL
@@ -4,24 +4,27 @@
 #include "interaction.glsl"
 #include "warp.glsl"
 
-float sample_position_pdf(in Instance instance, in PositionSample ps){
-    Mesh mesh = meshes[instance.mesh];
-    float pdf = 1.;
-    pdf *= 1. / ps.area;
-    pdf *= 1. / float(mesh.indices_count / 3);
-    pdf *= square_to_uniform_triangle_pdf(ps.barycentric.yz);
-    return pdf;
+// Triangles are sampled proportional to their area, uniform over the surface of the emitter.
+float sample_position_pdf(in Emitter emitter){
+    return emitter.area > 0.? 1. / emitter.area : 0.;
 }
-float sample_position_pdf(in Instance instance, in SurfaceInteraction si){
-    Mesh mesh = meshes[instance.mesh];
-    float pdf = 1.;
-    pdf *= 1. / si.area;
-    pdf *= 1. / float(mesh.indices_count / 3);
-    pdf *= square_to_uniform_triangle_pdf(si.barycentric.yz);
-    return pdf;
+
+// Index i of the triangle with triangle_cdf[offset + i] <= u < triangle_cdf[offset + i + 1].
+uint triangle_cdf_find_segment(uint offset, uint count, float u){
+    uint first = 0;
+    uint last = count;
+    while (last - first > 1){
+        uint middle = (first + last) / 2;
+        if (triangle_cdf[offset + middle] <= u){
+            first = middle;
+        }else{
+            last = middle;
+        }
+    }
+    return first;
 }
 
-PositionSample sample_position(in Instance instance, vec2 sample1){
+PositionSample sample_position(in Emitter emitter, in Instance instance, vec2 sample1){
     PositionSample ps;
     Mesh mesh = meshes[instance.mesh];
     
@@ -30,11 +33,15 @@ PositionSample sample_position(in Instance instance, vec2 sample1){
     // //DEBUG:
     // imageStore(image[0], ivec2(gl_LaunchIDEXT.xy), vec4(instance.mesh, mesh.indices_count, primitive_count, 0.));
         
-    uint primitive = sample_reuse(sample1.x, primitive_count);
-    ps.pdf = 1./float(primitive_count);
+    // Select a triangle proportional to its area and reuse the sample.
+    uint primitive = triangle_cdf_find_segment(emitter.triangle_cdf, primitive_count, sample1.x);
+    float cdf = triangle_cdf[emitter.triangle_cdf + primitive];
+    float width = triangle_cdf[emitter.triangle_cdf + primitive + 1] - cdf;
+    sample1.x = width > 0.? clamp((sample1.x - cdf) / width, 0., 1.) : 0.;
+    ps.pdf = sample_position_pdf(emitter);
     
+    // Uniform in the triangle
     vec2 b = square_to_uniform_triangle(sample1);
-    ps.pdf *= square_to_uniform_triangle_pdf(b);
     
     vec3 barycentric = vec3((1. - b.x -b.y), b.x, b.y);
 
@@ -54,7 +61,6 @@ PositionSample sample_position(in Instance instance, vec2 sample1){
     
     vec3 n = cross(p1 - p0, p2 - p0);
     ps.area = length(n)/2.;
-    ps.pdf *= 1./ps.area;
     ps.n = normalize(n);
     
     vec2 uv0 = uvs[mesh.uvs + triangle.x];
@@ -26,7 +26,7 @@ layout(set = 1, binding = 2, rgba32f) uniform image2D o_position;
 #include "trace.glsl"
 
 #include "sampler/independent.glsl"
-#include "bsdf/diffuse.glsl"
+#include "bsdf/principled.glsl"
 #include "camera.glsl"
 #include "emitter.glsl"
 
@@ -66,6 +66,7 @@ void main(){
     vec3 f = vec3(1.);
     uint depth = 0;
     float prev_bsdf_pdf = 1.;
+    bool prev_bsdf_delta = false;
     
     SurfaceInteraction si;
     
@@ -73,7 +74,12 @@ void main(){
         si = ray_intersect(ray);
 
         if (!si.valid){
-            // TODO: Constant emission
+            //===========================================================
+            // Environment Emission:
+            //===========================================================
+            float em_pdf = depth == 0?0.:pdf_env_direction(ray.d);
+            float mis_bsdf = prev_bsdf_delta? 1. : mis_weight(prev_bsdf_pdf, em_pdf);
+            L += f * eval_env_emitter(ray.d) * mis_bsdf;
             break;
         }
 
@@ -96,9 +102,10 @@ void main(){
         // Direct Emission:
         //===========================================================
 
-        float em_pdf = depth == 0?0.:pdf_emitter_direction(si);
+        float em_pdf = depth == 0?0.:pdf_emitter_direction(si, ray.o);
         
-        float mis_bsdf = mis_weight(prev_bsdf_pdf, em_pdf);
+        // Delta lobes cannot be hit by emitter sampling.
+        float mis_bsdf = prev_bsdf_delta? 1. : mis_weight(prev_bsdf_pdf, em_pdf);
 
         vec3 direct_emission = eval_emitter(si);
         
@@ -115,7 +122,8 @@ void main(){
         float em_bsdf_pdf;
         bsdf_eval_pdf(si, to_local(si, ds.d), em_bsdf_weight, em_bsdf_pdf);
 
-        float mis_em = mis_weight(ds.pdf, em_bsdf_pdf);
+        // Punctual lights cannot be hit by BSDF sampling.
+        float mis_em = ds.delta? 1. : mis_weight(ds.pdf, em_bsdf_pdf);
 
         L += f * em_weight * em_bsdf_weight * mis_em;
         
@@ -126,6 +134,7 @@ void main(){
         f *= bsdf_value;
         ray = spawn_ray(si, to_world(si, bs.wo));
         prev_bsdf_pdf = bs.pdf;
+        prev_bsdf_delta = bs.delta;
         
         //===========================================================
         // Russian Roulette:
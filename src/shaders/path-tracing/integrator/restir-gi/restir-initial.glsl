@@ -29,7 +29,7 @@ layout(set = 1, binding = 3) buffer Emittance{
 #include "trace.glsl"
 
 #include "sampler/independent.glsl"
-#include "bsdf/diffuse.glsl"
+#include "bsdf/principled.glsl"
 #include "camera.glsl"
 #include "emitter.glsl"
 
@@ -55,6 +55,20 @@ void main(){
 
     SurfaceInteraction si = ray_intersect(ray); // Trace to find x_v
 
+    if (!si.valid){
+        // Only the environment is visible
+        emittance[pixel_idx] = vec4(eval_env_emitter(ray.d), 1.);
+        S.x_v = ray.o + ray.d * ray.tmax;
+        S.n_v = -ray.d;
+        S.x_s = S.x_v;
+        S.n_s = S.n_v;
+        S.p_q = 0.;
+        S.f = vec3(0.);
+        S.L_o = vec3(0.);
+        initial_samples[pixel_idx] = S;
+        return;
+    }
+
     S.x_v = si.p;
     S.n_v = si.n;
 
@@ -72,10 +86,19 @@ void main(){
 
     si = ray_intersect(ray); // Trace to find x_s
 
-    S.x_s = si.p;
-    S.n_s = si.n;
+    vec3 Lo;
+    if (si.valid){
+        S.x_s = si.p;
+        S.n_s = si.n;
+
+        Lo = sample_outgoing(si, sample_generator);
+    }else{
+        // Environment at x_s, placed far away along the sampled direction.
+        S.x_s = ray.o + ray.d * ray.tmax;
+        S.n_s = -ray.d;
 
-    vec3 Lo = sample_outgoing(si, sample_generator);
+        Lo = eval_env_emitter(ray.d);
+    }
 
     S.L_o = Lo;
 
@@ -17,6 +17,7 @@ vec3 sample_outgoing(in SurfaceInteraction si, inout SampleGenerator sample_gene
     vec3 f = vec3(1.);
     uint depth = 0;
     float prev_bsdf_pdf = 1.;
+    bool prev_bsdf_delta = false;
 
     Ray ray;
     
@@ -26,7 +27,15 @@ vec3 sample_outgoing(in SurfaceInteraction si, inout SampleGenerator sample_gene
         }
 
         if (!si.valid){
-            // TODO: Constant emission
+            //===========================================================
+            // Environment Emission:
+            //===========================================================
+            // A miss at depth 0 is handled by the caller, which knows the direction.
+            if (depth > 0){
+                float em_pdf = pdf_env_direction(ray.d);
+                float mis_bsdf = prev_bsdf_delta? 1. : mis_weight(prev_bsdf_pdf, em_pdf);
+                L += f * eval_env_emitter(ray.d) * mis_bsdf;
+            }
             break;
         }
 
@@ -41,9 +50,10 @@ vec3 sample_outgoing(in SurfaceInteraction si, inout SampleGenerator sample_gene
         // Direct Emission:
         //===========================================================
 
-        float em_pdf = depth == 0?0.:pdf_emitter_direction(si);
+        float em_pdf = depth == 0?0.:pdf_emitter_direction(si, ray.o);
         
-        float mis_bsdf = mis_weight(prev_bsdf_pdf, em_pdf);
+        // Delta lobes cannot be hit by emitter sampling.
+        float mis_bsdf = prev_bsdf_delta? 1. : mis_weight(prev_bsdf_pdf, em_pdf);
 
         vec3 direct_emission = eval_emitter(si);
         
@@ -60,7 +70,8 @@ vec3 sample_outgoing(in SurfaceInteraction si, inout SampleGenerator sample_gene
         float em_bsdf_pdf;
         bsdf_eval_pdf(si, to_local(si, ds.d), em_bsdf_weight, em_bsdf_pdf);
 
-        float mis_em = mis_weight(ds.pdf, em_bsdf_pdf);
+        // Punctual lights cannot be hit by BSDF sampling.
+        float mis_em = ds.delta? 1. : mis_weight(ds.pdf, em_bsdf_pdf);
 
         L += f * em_weight * em_bsdf_weight * mis_em;
         
@@ -71,6 +82,7 @@ vec3 sample_outgoing(in SurfaceInteraction si, inout SampleGenerator sample_gene
         f *= bsdf_value;
         ray = spawn_ray(si, to_world(si, bs.wo));
         prev_bsdf_pdf = bs.pdf;
+        prev_bsdf_delta = bs.delta;
         
         //===========================================================
         // Russian Roulette:
@@ -26,7 +26,7 @@ layout(std140, set = 1, binding = 2) buffer SpatialReservoir{
 #include "trace.glsl"
 
 #include "sampler/independent.glsl"
-#include "bsdf/diffuse.glsl"
+#include "bsdf/principled.glsl"
 #include "camera.glsl"
 #include "emitter.glsl"
 
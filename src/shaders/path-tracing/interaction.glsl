@@ -61,15 +61,17 @@ Emitter emitter(in SurfaceInteraction si){
     }
     return emitter;
 }
+// Emitted radiance of a material: factor * texture * strength (the strength is folded into
+// emission_factor by the loader).
+vec3 eval_emission(in Material material, vec2 uv){
+    return eval_texture(material.emission, uv) * material.emission_factor;
+}
 vec3 eval_emitter(in SurfaceInteraction si){
     Instance instance = instances[si.instance];
     if(instance.emitter == -1){
         return vec3(0., 0., 0.);
     }else{
-        Emitter emitter = emitters[instance.emitter];
-        vec3 emission = eval_texture(emitter.emission, si.uv);
-
-        return emission;
+        return eval_emission(si.material, si.uv);
     }
     return vec3(0., 0., 0.);
 }
@@ -95,13 +97,6 @@ void finalize_surface_interaction(inout SurfaceInteraction si, in Ray ray){
     si.n = cross(p1 - p0, p2 - p0);
     si.area = length(si.n);
     si.n = si.n / si.area;
-    
-    // vec3 n0 = normals[mesh.normals + triangle.x];
-    // vec3 n1 = normals[mesh.normals + triangle.y];
-    // vec3 n2 = normals[mesh.normals + triangle.z];
-    //
-    // vec3 n = n0 * si.barycentric.x + n1 * si.barycentric.y + n2 * si.barycentric.z;
-    // si.n = normalize(inverse(transpose(mat3(instance.to_world))) * n);
 
     vec2 uv0 = uvs[mesh.uvs + triangle.x];
     vec2 uv1 = uvs[mesh.uvs + triangle.y];
@@ -109,9 +104,37 @@ void finalize_surface_interaction(inout SurfaceInteraction si, in Ray ray){
 
     vec2 uv = uv0 * si.barycentric.x + uv1 * si.barycentric.y + uv2 * si.barycentric.z;
     si.uv = uv;
-        
-    mat3 tbn = compute_TBN(uv1 - uv0, uv2 - uv0, p1 - p0, p2 - p0, si.n);
-    si.tbn = tbn;
+
+    //===========================================================
+    // Shading frame:
+    //===========================================================
+    mat3 normal_to_world = transpose(inverse(mat3(instance.to_world)));
+
+    vec3 n0 = normals[mesh.normals + triangle.x];
+    vec3 n1 = normals[mesh.normals + triangle.y];
+    vec3 n2 = normals[mesh.normals + triangle.z];
+    vec3 n = normalize(normal_to_world * (n0 * si.barycentric.x + n1 * si.barycentric.y + n2 * si.barycentric.z));
+
+    vec4 t0 = tangents[mesh.tangents + triangle.x];
+    vec4 t1 = tangents[mesh.tangents + triangle.y];
+    vec4 t2 = tangents[mesh.tangents + triangle.z];
+    vec4 t = t0 * si.barycentric.x + t1 * si.barycentric.y + t2 * si.barycentric.z;
+
+    // Gram-Schmidt, the tangent is not guaranteed to be orthogonal after interpolation.
+    vec3 tangent = mat3(instance.to_world) * t.xyz;
+    tangent = normalize(tangent - n * dot(n, tangent));
+    vec3 bitangent = cross(n, tangent) * (t.w < 0.? -1. : 1.);
+
+    if (material.normal.ty == TEXTURE_TY_IMAGE){
+        vec3 n_local = eval_texture(material.normal, si.uv) * 2. - 1.;
+        n_local.xy *= material.normal_scale;
+        n = normalize(mat3(tangent, bitangent, n) * n_local);
+
+        tangent = normalize(tangent - n * dot(n, tangent));
+        bitangent = cross(n, tangent) * (t.w < 0.? -1. : 1.);
+    }
+
+    si.tbn = mat3(tangent, bitangent, n);
 
     si.wi = to_local(si, -ray.d);
     
@@ -0,0 +1,121 @@
+#ifndef LIGHT_BVH_GLSL
+#define LIGHT_BVH_GLSL
+
+#include "math.glsl"
+
+// Stochastic traversal of the light BVH built in src/light_bvh.rs.
+// Children are chosen proportional to the importance of their bounds for the point p.
+
+// cos(max(0, a - b)) and sin(max(0, a - b)) from sines and cosines.
+float cos_sub_clamped(float sin_a, float cos_a, float sin_b, float cos_b){
+    if (cos_a > cos_b){
+        return 1.;
+    }
+    return cos_a * cos_b + sin_a * sin_b;
+}
+float sin_sub_clamped(float sin_a, float cos_a, float sin_b, float cos_b){
+    if (cos_a > cos_b){
+        return 0.;
+    }
+    return sin_a * cos_b - cos_a * sin_b;
+}
+
+float light_bvh_importance(in LightBvhNode node, vec3 p){
+    vec3 pc = 0.5 * (node.min + node.max);
+    float dist2 = dot(p - pc, p - pc);
+    // Avoid the singularity when p is inside the bounds.
+    float d2 = max(dist2, length(node.max - node.min) / 2.);
+
+    // Angle between the cone axis and the direction to p.
+    vec3 wi = dist2 > 0.? (p - pc) / sqrt(dist2) : vec3(0., 0., 1.);
+    float cos_theta_w = dot(node.axis, wi);
+    if (node.two_sided != 0){
+        cos_theta_w = abs(cos_theta_w);
+    }
+    float sin_theta_w = sqrt(max(0., 1. - cos_theta_w * cos_theta_w));
+
+    // Angle subtended by the bounding sphere.
+    float radius2 = dot(node.max - pc, node.max - pc);
+    float cos_theta_b = dist2 < radius2? -1. : sqrt(max(0., 1. - radius2 / dist2));
+    float sin_theta_b = sqrt(max(0., 1. - cos_theta_b * cos_theta_b));
+
+    float cos_theta_o = node.cos_theta_o;
+    float sin_theta_o = sqrt(max(0., 1. - cos_theta_o * cos_theta_o));
+
+    // Minimal angle between the emitted directions and the direction to p.
+    float cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+    float sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+    float cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+    if (cos_theta_p <= node.cos_theta_e){
+        return 0.;
+    }
+
+    return node.power * cos_theta_p / d2;
+}
+
+// Probability of the first child, returns -1 if neither child contributes.
+float light_bvh_first_child_prob(in LightBvhNode node, uint node_idx, vec3 p){
+    float importance_first = light_bvh_importance(light_bvh[node_idx + 1], p);
+    float importance_second = light_bvh_importance(light_bvh[node.index], p);
+    if (importance_first + importance_second <= 0.){
+        return -1.;
+    }
+    return importance_first / (importance_first + importance_second);
+}
+
+// Samples an emitter of the light BVH, sample1 is rescaled for reuse.
+bool sample_light_bvh(vec3 p, inout float sample1, out uint emitter, out float pdf){
+    uint node_idx = 0;
+    pdf = 1.;
+    LightBvhNode node = light_bvh[0];
+    if (node.leaf != 0 && light_bvh_importance(node, p) <= 0.){
+        return false;
+    }
+    while (node.leaf == 0){
+        float p_first = light_bvh_first_child_prob(node, node_idx, p);
+        if (p_first < 0.){
+            return false;
+        }
+        if (sample1 < p_first){
+            sample1 = sample1 / p_first;
+            pdf *= p_first;
+            node_idx = node_idx + 1;
+        }else{
+            sample1 = (sample1 - p_first) / (1. - p_first);
+            pdf *= 1. - p_first;
+            node_idx = node.index;
+        }
+        node = light_bvh[node_idx];
+    }
+    emitter = node.index;
+    return true;
+}
+
+// Probability of sampling the emitter with sample_light_bvh.
+float pdf_light_bvh(uint emitter, vec3 p){
+    uint trail = light_bvh_trails[emitter];
+    uint node_idx = 0;
+    float pdf = 1.;
+    LightBvhNode node = light_bvh[0];
+    if (node.leaf != 0 && light_bvh_importance(node, p) <= 0.){
+        return 0.;
+    }
+    while (node.leaf == 0){
+        float p_first = light_bvh_first_child_prob(node, node_idx, p);
+        if (p_first < 0.){
+            return 0.;
+        }
+        if ((trail & 1) == 0){
+            pdf *= p_first;
+            node_idx = node_idx + 1;
+        }else{
+            pdf *= 1. - p_first;
+            node_idx = node.index;
+        }
+        trail >>= 1;
+        node = light_bvh[node_idx];
+    }
+    return pdf;
+}
+
+#endif //LIGHT_BVH_GLSL
@@ -27,6 +27,8 @@ struct DirectionSample{
     
     vec3 d;
     float dist;
+    // Sampled from a punctual light, which BSDF sampling cannot hit.
+    bool delta;
 };
 
 DirectionSample direction_sample(in SurfaceInteraction si){
@@ -39,6 +41,7 @@ DirectionSample direction_sample(in SurfaceInteraction si){
     ds.tbn = si.tbn;
     ds.d = -to_world(si, si.wi);
     ds.dist = si.dist;
+    ds.delta = false;
     return ds;
 }
 
@@ -50,6 +53,7 @@ DirectionSample direction_sample(in PositionSample ps){
     ds.pdf = ps.pdf;
     ds.barycentric = ps.barycentric;
     ds.tbn = ps.tbn;
+    ds.delta = false;
     return ds;
 }
 
@@ -37,6 +37,28 @@ layout(std140, set = 0, binding = 8) buffer Cameras{
     Camera cameras[];
 };
 layout(set = 0, binding = 9) uniform sampler2D textures[];
+layout(std140, set = 0, binding = 11) buffer Tangents{
+    vec4 tangents[];
+};
+// Conditional CDFs (one per row) followed by the marginal CDF of the environment map.
+layout(set = 0, binding = 12) buffer EnvCdf{
+    float env_cdf[];
+};
+// Alias table for sampling emitters proportional to their power.
+layout(std140, set = 0, binding = 13) buffer EmitterAlias{
+    AliasEntry emitter_alias[];
+};
+layout(std140, set = 0, binding = 14) buffer LightBvh{
+    LightBvhNode light_bvh[];
+};
+// Path from the root to the leaf of each emitter in the light BVH.
+layout(set = 0, binding = 15) buffer LightBvhTrails{
+    uint light_bvh_trails[];
+};
+// CDFs over the triangle areas of area emitters.
+layout(set = 0, binding = 16) buffer TriangleCdf{
+    float triangle_cdf[];
+};
 
 // layout(push_constant) uniform PushConstants{
 //     uint camera;
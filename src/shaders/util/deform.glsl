@@ -0,0 +1,100 @@
+#version 460
+
+// Applies morph targets and then skinning to the rest pose of a mesh and writes the result into
+// the scene vertex buffers, one invocation per vertex. See `Deformer` in deform.rs.
+
+layout(local_size_x = 64) in;
+
+layout(std140, set = 0, binding = 0) buffer Positions{
+    vec3 positions[];
+};
+layout(std140, set = 0, binding = 1) buffer Normals{
+    vec3 normals[];
+};
+layout(std140, set = 0, binding = 2) buffer Tangents{
+    vec4 tangents[];
+};
+
+layout(std140, set = 0, binding = 3) buffer RestPositions{
+    vec3 rest_positions[];
+};
+layout(std140, set = 0, binding = 4) buffer RestNormals{
+    vec3 rest_normals[];
+};
+layout(std140, set = 0, binding = 5) buffer RestTangents{
+    vec4 rest_tangents[];
+};
+
+struct SkinVertex{
+    vec4 joints;
+    vec4 weights;
+};
+layout(std140, set = 0, binding = 6) buffer SkinVertices{
+    SkinVertex skin_vertices[];
+};
+layout(std140, set = 0, binding = 7) buffer JointMatrices{
+    mat4 joint_matrices[];
+};
+
+// Deltas of all morph targets of a mesh, target by target.
+layout(std140, set = 0, binding = 8) buffer MorphPositions{
+    vec3 morph_positions[];
+};
+layout(std140, set = 0, binding = 9) buffer MorphNormals{
+    vec3 morph_normals[];
+};
+layout(std140, set = 0, binding = 10) buffer MorphTangents{
+    vec3 morph_tangents[];
+};
+layout(set = 0, binding = 11) buffer MorphWeights{
+    float morph_weights[];
+};
+
+layout(push_constant) uniform PushConstants{
+    uint mesh;
+    uint positions_offset;
+    uint normals_offset;
+    uint tangents_offset;
+    uint vertex_count;
+    uint rest;
+    uint joints;
+    uint joint_count;
+    uint morph;
+    uint weights;
+    uint target_count;
+};
+
+void main(){
+    uint i = gl_GlobalInvocationID.x;
+    if (i >= vertex_count){
+        return;
+    }
+
+    vec3 p = rest_positions[rest + i];
+    vec3 n = rest_normals[rest + i];
+    vec4 t = rest_tangents[rest + i];
+
+    for (uint target = 0; target < target_count; target++){
+        float w = morph_weights[weights + target];
+        uint j = morph + target * vertex_count + i;
+        p += w * morph_positions[j];
+        n += w * morph_normals[j];
+        t.xyz += w * morph_tangents[j];
+    }
+
+    if (joint_count > 0){
+        SkinVertex v = skin_vertices[rest + i];
+        mat4 skin = mat4(0.);
+        for (uint k = 0; k < 4; k++){
+            skin += v.weights[k] * joint_matrices[joints + min(uint(v.joints[k]), joint_count - 1)];
+        }
+        p = (skin * vec4(p, 1.)).xyz;
+        // Joints are expected to scale uniformly, the inverse transpose is not needed then.
+        n = mat3(skin) * n;
+        t.xyz = mat3(skin) * t.xyz;
+    }
+
+    positions[positions_offset + i] = p;
+    normals[normals_offset + i] = normalize(n);
+    tangents[tangents_offset + i] = vec4(normalize(t.xyz), t.w);
+}
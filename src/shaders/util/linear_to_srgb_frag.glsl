@@ -6,6 +6,10 @@ layout(set = 0, binding = 0) uniform sampler2D image_sampler_llr;
 
 layout(location = 0) out vec4 o_color;
 
+layout(push_constant) uniform PushConstants{
+    float exposure;
+};
+
 vec4 linear_to_srgb(vec4 linear)
 {
     bvec4 cutoff = lessThan(linear, vec4(0.0031308));
@@ -20,6 +24,6 @@ void main(){
     color = color/(color + vec3(1.));
     color = pow(color, vec3(1./2.2));
     o_color = vec4(color, 1.);
-    o_color = linear_to_srgb(texture(image_sampler_llr, i_uv));
+    o_color = linear_to_srgb(texture(image_sampler_llr, i_uv) * exp2(exposure));
     //o_color = vec4(1., 0., 0., 1.);
 }
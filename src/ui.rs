@@ -0,0 +1,143 @@
+use crate::scene::Scene;
+use crate::settings::{RendererType, Settings};
+
+pub struct SceneStats {
+    pub instances: usize,
+    pub triangles: usize,
+    pub emitters: usize,
+    pub textures: usize,
+    pub cameras: usize,
+    /// BLAS memory in bytes before and after compaction, known once compacted.
+    pub blas_memory: Option<(u64, u64)>,
+    /// Names of the animation clips, by index over all files.
+    pub clips: Vec<String>,
+}
+
+impl SceneStats {
+    pub fn new(scene: &Scene) -> Self {
+        Self {
+            instances: scene.instances.len(),
+            triangles: scene
+                .instances
+                .iter()
+                .map(|instance| scene.meshes[instance.mesh as usize].indices_count as usize / 3)
+                .sum(),
+            emitters: scene.emitters.len(),
+            textures: scene.textures.len(),
+            cameras: scene.cameras.len(),
+            blas_memory: None,
+            clips: clip_names(scene),
+        }
+    }
+}
+
+///
+/// Names clips by the first file that has a clip of that index, unnamed clips by their index.
+///
+fn clip_names(scene: &Scene) -> Vec<String> {
+    let count = scene
+        .animations
+        .iter()
+        .map(|animation| animation.clips.len())
+        .max()
+        .unwrap_or(0);
+    (0..count)
+        .map(|i| {
+            scene
+                .animations
+                .iter()
+                .find_map(|animation| animation.clips.get(i))
+                .and_then(|clip| clip.name.clone())
+                .unwrap_or_else(|| format!("{}", i))
+        })
+        .collect()
+}
+
+///
+/// Actions requested through the control panel.
+///
+#[derive(Default)]
+pub struct UiResponse {
+    /// Accumulation has to restart.
+    pub reset: bool,
+    pub renderer_changed: bool,
+    pub camera_changed: bool,
+    pub save_screenshot: bool,
+}
+
+pub fn control_panel(
+    ctx: &egui::Context,
+    settings: &mut Settings,
+    stats: &SceneStats,
+    dt: f32,
+    frame_count: u32,
+) -> UiResponse {
+    let mut response = UiResponse::default();
+
+    egui::Window::new("Renderer").show(ctx, |ui| {
+        ui.label(format!("Frame time: {:.2} ms", dt * 1000.));
+        ui.label(format!("Samples: {}", frame_count));
+
+        ui.separator();
+        ui.label(format!("Instances: {}", stats.instances));
+        ui.label(format!("Triangles: {}", stats.triangles));
+        ui.label(format!("Emitters: {}", stats.emitters));
+        ui.label(format!("Textures: {}", stats.textures));
+        if let Some((before, after)) = stats.blas_memory {
+            let mib = |bytes: u64| bytes as f64 / (1 << 20) as f64;
+            ui.label(format!(
+                "BLAS memory: {:.1} MiB (compacted from {:.1} MiB)",
+                mib(after),
+                mib(before)
+            ));
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            response.renderer_changed |= ui
+                .radio_value(&mut settings.renderer, RendererType::Pt, "PT")
+                .changed();
+            response.renderer_changed |= ui
+                .radio_value(&mut settings.renderer, RendererType::Restir, "ReSTIR")
+                .changed();
+        });
+        response.reset |= ui
+            .add(egui::Slider::new(&mut settings.max_depth, 1..=32).text("Max depth"))
+            .changed();
+        response.reset |= ui
+            .add(egui::Slider::new(&mut settings.rr_depth, 0..=32).text("RR depth"))
+            .changed();
+        ui.add(egui::Slider::new(&mut settings.exposure, -10. ..=10.).text("Exposure"));
+
+        egui::ComboBox::from_label("Camera")
+            .selected_text(format!("{}", settings.camera))
+            .show_ui(ui, |ui| {
+                for camera in 0..stats.cameras as u32 {
+                    response.camera_changed |= ui
+                        .selectable_value(&mut settings.camera, camera, format!("{}", camera))
+                        .changed();
+                }
+            });
+        if !stats.clips.is_empty() {
+            egui::ComboBox::from_label("Animation")
+                .selected_text(stats.clips.get(settings.clip).map_or("", String::as_str))
+                .show_ui(ui, |ui| {
+                    for (clip, name) in stats.clips.iter().enumerate() {
+                        response.reset |= ui
+                            .selectable_value(&mut settings.clip, clip, name.as_str())
+                            .changed();
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.play, "Play animation");
+                ui.label(format!("{:.2} s", settings.time));
+            });
+        }
+
+        ui.separator();
+        response.save_screenshot = ui.button("Save screenshot").clicked();
+    });
+
+    response.reset |= response.renderer_changed || response.camera_changed;
+    response
+}